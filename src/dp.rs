@@ -0,0 +1,1155 @@
+//! Alignment fills that `seal` does not provide, producing `seal` alignments so results can
+//! be traced and scored like any other.
+
+use seal::pair::{Alignment, Cursor, StepMask};
+use std::time::{Duration, Instant};
+
+/// Work done by fills, accumulated across calls.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Stats {
+    pub cells: usize,
+    pub fill: Duration,
+    pub traceback: Duration,
+}
+
+/// Gap scores, which may differ for gaps before the first or after the last token of either
+/// sequence. The first token of an interior gap run scores `open_score` and every other one
+/// `score`; the first token of a terminal run scores `end_open_score` and every other one
+/// `end_score`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Gaps {
+    pub score: isize,
+    pub open_score: isize,
+    pub end_score: isize,
+    pub end_open_score: isize,
+}
+
+impl Gaps {
+    pub(crate) fn linear(score: isize) -> Gaps {
+        Gaps {
+            score,
+            open_score: score,
+            end_score: score,
+            end_open_score: score,
+        }
+    }
+
+    pub(crate) fn is_uniform(&self) -> bool {
+        self.score == self.end_score && !self.is_affine()
+    }
+
+    /// Whether opening a gap run scores differently from extending it.
+    pub(crate) fn is_affine(&self) -> bool {
+        self.open_score != self.score || self.end_open_score != self.end_score
+    }
+
+    pub(crate) fn scores(&self) -> impl Iterator<Item = isize> {
+        [
+            self.score,
+            self.open_score,
+            self.end_score,
+            self.end_open_score,
+        ]
+        .into_iter()
+    }
+
+    /// Score of deleting a token to reach row `y` of `y_len`, opening a gap run.
+    pub(crate) fn delete_open(&self, y: usize, y_len: usize) -> isize {
+        if y == 0 || y == y_len {
+            self.end_open_score
+        } else {
+            self.open_score
+        }
+    }
+
+    /// Score of inserting a token to reach column `x` of `x_len`, opening a gap run.
+    pub(crate) fn insert_open(&self, x: usize, x_len: usize) -> isize {
+        if x == 0 || x == x_len {
+            self.end_open_score
+        } else {
+            self.open_score
+        }
+    }
+
+    /// Score of deleting a token to reach row `y` of `y_len`, extending a gap run.
+    pub(crate) fn delete(&self, y: usize, y_len: usize) -> isize {
+        if y == 0 || y == y_len {
+            self.end_score
+        } else {
+            self.score
+        }
+    }
+
+    /// Score of inserting a token to reach column `x` of `x_len`, extending a gap run.
+    pub(crate) fn insert(&self, x: usize, x_len: usize) -> isize {
+        if x == 0 || x == x_len {
+            self.end_score
+        } else {
+            self.score
+        }
+    }
+}
+
+/// Per-row column ranges `lo..=hi` a fill is restricted to.
+pub(crate) struct Band {
+    ranges: Vec<(usize, usize)>,
+}
+
+impl Band {
+    /// Covers every cell.
+    pub(crate) fn full(x_len: usize, y_len: usize) -> Band {
+        Band {
+            ranges: vec![(0, x_len); y_len + 1],
+        }
+    }
+
+    /// Covers `width` cells either side of the diagonal joining `(0, 0)` and
+    /// `(x_len, y_len)`.
+    pub(crate) fn diagonal(x_len: usize, y_len: usize, width: usize) -> Band {
+        if y_len == 0 {
+            return Band {
+                ranges: vec![(0, x_len)],
+            };
+        }
+        let ranges = (0..=y_len)
+            .map(|y| {
+                let lo = (y * x_len / y_len).saturating_sub(width);
+                let hi = ((y + 1) * x_len).div_ceil(y_len) + width;
+                (lo, hi.min(x_len))
+            })
+            .collect();
+        Band { ranges }
+    }
+
+    /// Covers `width` cells either side of the path `masks` take from `(0, 0)` to
+    /// `(x_len, y_len)`, in either direction.
+    pub(crate) fn around(x_len: usize, y_len: usize, masks: &[StepMask], width: usize) -> Band {
+        let mut ranges = vec![(usize::MAX, 0); y_len + 1];
+        let mut cover = |cursor: Cursor| {
+            for range in &mut ranges[cursor.y.saturating_sub(width)..=(cursor.y + width).min(y_len)]
+            {
+                range.0 = range.0.min(cursor.x.saturating_sub(width));
+                range.1 = range.1.max((cursor.x + width).min(x_len));
+            }
+        };
+        let mut cursor = Cursor { x: 0, y: 0 };
+        cover(cursor);
+        for mask in masks {
+            cursor.apply_forwards_step(*mask);
+            cover(cursor);
+        }
+        Band { ranges }
+    }
+}
+
+/// Traceback order of `seal`, which prefers deletions, then insertions, then alignments.
+pub(crate) const SEAL_ORDER: [StepMask; 3] = [StepMask::DELETE, StepMask::INSERT, StepMask::ALIGN];
+
+/// Globally aligns `x_len` against `y_len` tokens within `band`, scoring aligned pairs with
+/// `score` and gaps with `gaps`. Ties are broken like `seal`.
+pub(crate) fn global<F>(
+    x_len: usize,
+    y_len: usize,
+    band: &Band,
+    gaps: Gaps,
+    stats: &mut Stats,
+    score: F,
+) -> Alignment
+where
+    F: Fn(usize, usize) -> isize,
+{
+    global_preferring(x_len, y_len, band, gaps, SEAL_ORDER, stats, score)
+}
+
+/// Like [`global`], but breaks ties by taking the first step in `order` while tracing back
+/// from the end of the alignment.
+pub(crate) fn global_preferring<F>(
+    x_len: usize,
+    y_len: usize,
+    band: &Band,
+    gaps: Gaps,
+    order: [StepMask; 3],
+    stats: &mut Stats,
+    score: F,
+) -> Alignment
+where
+    F: Fn(usize, usize) -> isize,
+{
+    let start = Instant::now();
+    let mut masks: Vec<Vec<StepMask>> = Vec::with_capacity(y_len + 1);
+    let mut previous: Vec<Option<isize>> = vec![];
+    let mut previous_lo = 0;
+
+    for (y, &(lo, hi)) in band.ranges.iter().enumerate() {
+        let mut row: Vec<Option<isize>> = Vec::with_capacity(hi - lo + 1);
+        let mut row_masks = Vec::with_capacity(hi - lo + 1);
+        let above = |x: usize| -> Option<isize> {
+            if y == 0 || x < previous_lo {
+                return None;
+            }
+            previous.get(x - previous_lo).copied().flatten()
+        };
+
+        for x in lo..=hi {
+            if x == 0 && y == 0 {
+                row.push(Some(0));
+                row_masks.push(StepMask::STOP);
+                continue;
+            }
+            let align = if x > 0 {
+                above(x - 1).map(|s| s + score(x - 1, y - 1))
+            } else {
+                None
+            };
+            let delete = if x > lo {
+                row[x - 1 - lo].map(|s| s + gaps.delete(y, y_len))
+            } else {
+                None
+            };
+            let insert = above(x).map(|s| s + gaps.insert(x, x_len));
+
+            let best = [delete, insert, align].into_iter().flatten().max();
+            let mut mask = StepMask::STOP;
+            for (step, candidate) in [
+                (StepMask::DELETE, delete),
+                (StepMask::INSERT, insert),
+                (StepMask::ALIGN, align),
+            ] {
+                if candidate.is_some() && candidate == best {
+                    mask.insert(step);
+                }
+            }
+            row_masks.push(mask);
+            row.push(best);
+        }
+
+        stats.cells += row.len();
+        masks.push(row_masks);
+        previous = row;
+        previous_lo = lo;
+    }
+    stats.fill += start.elapsed();
+
+    let start = Instant::now();
+    let score = previous.last().copied().flatten().unwrap_or(0);
+    let mut steps = vec![];
+    let mut cursor = Cursor { x: x_len, y: y_len };
+    while cursor.x > 0 || cursor.y > 0 {
+        let mask = masks[cursor.y][cursor.x - band.ranges[cursor.y].0];
+        let step = order
+            .into_iter()
+            .find(|step| mask.contains(*step))
+            .expect("every cell inside the band is reachable");
+        steps.push(step);
+        cursor.apply_backwards_step(step);
+    }
+    steps.reverse();
+    stats.traceback += start.elapsed();
+
+    Alignment::new(Cursor { x: 0, y: 0 }, steps, score)
+}
+
+/// A global fill of `x_len` tokens against a sequence that grows one token at a time, keeping
+/// each row of the fill so that pushing a token only computes its own row. Gaps score `gap`
+/// and ties are broken like `seal`.
+#[derive(Clone)]
+pub(crate) struct GrowingGlobal {
+    gap: isize,
+    row: Vec<isize>,
+    masks: Vec<Vec<StepMask>>,
+}
+
+impl GrowingGlobal {
+    pub(crate) fn new(x_len: usize, gap: isize) -> GrowingGlobal {
+        let row = (0..=x_len as isize).map(|x| x * gap).collect();
+        let mut masks = vec![StepMask::DELETE; x_len + 1];
+        masks[0] = StepMask::STOP;
+        GrowingGlobal {
+            gap,
+            row,
+            masks: vec![masks],
+        }
+    }
+
+    /// Fills the row of a new token, scoring its alignment to token `x` with `score(x)`.
+    pub(crate) fn push<F>(&mut self, stats: &mut Stats, score: F)
+    where
+        F: Fn(usize) -> isize,
+    {
+        let start = Instant::now();
+        let mut row = Vec::with_capacity(self.row.len());
+        let mut masks = Vec::with_capacity(self.row.len());
+        row.push(self.row[0] + self.gap);
+        masks.push(StepMask::INSERT);
+        for x in 1..self.row.len() {
+            let (value, mask) = [
+                (row[x - 1] + self.gap, StepMask::DELETE),
+                (self.row[x] + self.gap, StepMask::INSERT),
+                (self.row[x - 1] + score(x - 1), StepMask::ALIGN),
+            ]
+            .into_iter()
+            .rev()
+            .max_by_key(|(value, _)| *value)
+            .expect("there are three candidates");
+            row.push(value);
+            masks.push(mask);
+        }
+        stats.cells += row.len();
+        stats.fill += start.elapsed();
+        self.row = row;
+        self.masks.push(masks);
+    }
+
+    /// Score of the best alignment to the tokens pushed so far.
+    pub(crate) fn score(&self) -> isize {
+        *self.row.last().expect("rows always have a cell")
+    }
+
+    /// Traces the best alignment to the tokens pushed so far.
+    pub(crate) fn alignment(&self, stats: &mut Stats) -> Alignment {
+        let start = Instant::now();
+        let mut steps = vec![];
+        let mut cursor = Cursor {
+            x: self.row.len() - 1,
+            y: self.masks.len() - 1,
+        };
+        while cursor.x > 0 || cursor.y > 0 {
+            let step = self.masks[cursor.y][cursor.x];
+            steps.push(step);
+            cursor.apply_backwards_step(step);
+        }
+        steps.reverse();
+        stats.traceback += start.elapsed();
+        Alignment::new(Cursor { x: 0, y: 0 }, steps, self.score())
+    }
+}
+
+/// A fill of all `x_len` tokens against any stretch of `y_len`, as [`fit`] finds it.
+pub(crate) struct Fit {
+    width: usize,
+    masks: Vec<StepMask>,
+    scores: Vec<isize>,
+}
+
+impl Fit {
+    /// Score of the best alignment ending after each number of `y` tokens.
+    pub(crate) fn scores(&self) -> &[isize] {
+        &self.scores
+    }
+
+    /// Traces the best alignment ending after `y_end` tokens of `y`.
+    pub(crate) fn alignment(&self, y_end: usize, stats: &mut Stats) -> Alignment {
+        let start = Instant::now();
+        let mut cursor = Cursor {
+            x: self.width - 1,
+            y: y_end,
+        };
+        let mut steps = vec![];
+        loop {
+            let mask = self.masks[cursor.y * self.width + cursor.x];
+            if mask == StepMask::STOP {
+                break;
+            }
+            steps.push(mask);
+            cursor.apply_backwards_step(mask);
+        }
+        steps.reverse();
+        stats.traceback += start.elapsed();
+        Alignment::new(cursor, steps, self.scores[y_end])
+    }
+}
+
+/// Aligns all `x_len` tokens against every stretch of `y_len` tokens at once, leaving the
+/// tokens of `y` before and after each stretch unscored, with gaps scoring `gap_score`.
+/// Ties are broken like `seal`.
+pub(crate) fn fit<F>(
+    x_len: usize,
+    y_len: usize,
+    gap_score: isize,
+    stats: &mut Stats,
+    score: F,
+) -> Fit
+where
+    F: Fn(usize, usize) -> isize,
+{
+    let start = Instant::now();
+    let width = x_len + 1;
+    let mut masks = vec![StepMask::STOP; width * (y_len + 1)];
+    let mut previous: Vec<isize> = (0..=x_len as isize).map(|x| x * gap_score).collect();
+    let mut row = vec![0isize; width];
+    let mut scores = Vec::with_capacity(y_len + 1);
+    masks[1..width].fill(StepMask::DELETE);
+    scores.push(previous[x_len]);
+
+    for y in 1..=y_len {
+        row[0] = 0;
+        for x in 1..=x_len {
+            let align = previous[x - 1] + score(x - 1, y - 1);
+            let delete = row[x - 1] + gap_score;
+            let insert = previous[x] + gap_score;
+            let cell = align.max(delete).max(insert);
+            masks[y * width + x] = if delete == cell {
+                StepMask::DELETE
+            } else if insert == cell {
+                StepMask::INSERT
+            } else {
+                StepMask::ALIGN
+            };
+            row[x] = cell;
+        }
+        scores.push(row[x_len]);
+        std::mem::swap(&mut previous, &mut row);
+    }
+    stats.cells += width * (y_len + 1);
+    stats.fill += start.elapsed();
+
+    Fit {
+        width,
+        masks,
+        scores,
+    }
+}
+
+/// Fills the row of `y` tokens from the row before it, `previous`, into `row`, returning the
+/// step each cell is best reached by. Ties are broken like `seal`.
+fn linear_row<F>(
+    previous: &[isize],
+    row: &mut [isize],
+    y: usize,
+    y_len: usize,
+    gaps: Gaps,
+    score: &F,
+) -> Vec<StepMask>
+where
+    F: Fn(usize, usize) -> isize,
+{
+    let x_len = row.len() - 1;
+    let mut masks = Vec::with_capacity(row.len());
+    row[0] = previous[0] + gaps.insert(0, x_len);
+    masks.push(StepMask::INSERT);
+    for x in 1..=x_len {
+        let delete = row[x - 1] + gaps.delete(y, y_len);
+        let insert = previous[x] + gaps.insert(x, x_len);
+        let align = previous[x - 1] + score(x - 1, y - 1);
+        let cell = align.max(delete).max(insert);
+        masks.push(if delete == cell {
+            StepMask::DELETE
+        } else if insert == cell {
+            StepMask::INSERT
+        } else {
+            StepMask::ALIGN
+        });
+        row[x] = cell;
+    }
+    masks
+}
+
+/// Globally aligns `x_len` against `y_len` tokens like [`global`] with linear `gaps`, but
+/// keeping only every `stride`th row of the fill and refilling the rows between two of them
+/// while tracing back through it. This holds `(y_len / stride + stride + 3) * (x_len + 1)`
+/// cells at once, against `(y_len + 1) * (x_len + 1)` for a full fill, at the cost of
+/// filling every row twice.
+pub(crate) fn global_checkpointed<F>(
+    x_len: usize,
+    y_len: usize,
+    gaps: Gaps,
+    stride: usize,
+    stats: &mut Stats,
+    score: F,
+) -> Alignment
+where
+    F: Fn(usize, usize) -> isize,
+{
+    let start = Instant::now();
+    let mut row: Vec<isize> = vec![0];
+    for x in 1..=x_len {
+        row.push(row[x - 1] + gaps.delete(0, y_len));
+    }
+    let mut checkpoints = vec![row.clone()];
+    let mut next = row.clone();
+    for y in 1..=y_len {
+        linear_row(&row, &mut next, y, y_len, gaps, &score);
+        std::mem::swap(&mut row, &mut next);
+        if y % stride == 0 {
+            checkpoints.push(row.clone());
+        }
+    }
+    stats.cells += (x_len + 1) * (y_len + 1);
+    stats.fill += start.elapsed();
+    let total = row[x_len];
+
+    let start = Instant::now();
+    let mut steps = vec![];
+    let mut cursor = Cursor { x: x_len, y: y_len };
+    while cursor.y > 0 {
+        let checkpoint = (cursor.y - 1) / stride;
+        let first = checkpoint * stride;
+        row.copy_from_slice(&checkpoints[checkpoint]);
+        let mut masks = Vec::with_capacity(cursor.y - first);
+        for y in first + 1..=cursor.y {
+            masks.push(linear_row(&row, &mut next, y, y_len, gaps, &score));
+            std::mem::swap(&mut row, &mut next);
+        }
+        stats.cells += (x_len + 1) * (cursor.y - first);
+        while cursor.y > first {
+            let step = masks[cursor.y - first - 1][cursor.x];
+            steps.push(step);
+            cursor.apply_backwards_step(step);
+        }
+    }
+    steps.extend(std::iter::repeat_n(StepMask::DELETE, cursor.x));
+    steps.reverse();
+    stats.traceback += start.elapsed();
+
+    Alignment::new(Cursor { x: 0, y: 0 }, steps, total)
+}
+
+/// Score paired with a tie-breaking value, compared in that order.
+type Value = Option<(isize, isize)>;
+
+const ALIGNED: usize = 0;
+const DELETED: usize = 1;
+const INSERTED: usize = 2;
+
+/// Picks the best of extending each state's value by its `costs`, preferring deletions, then
+/// insertions, then alignments among equals.
+fn extend(values: &[Value; 3], costs: [(isize, isize); 3]) -> (Value, u8) {
+    let mut best: (Value, u8) = (None, 0);
+    for state in [DELETED, INSERTED, ALIGNED] {
+        let candidate =
+            values[state].map(|(score, tie)| (score + costs[state].0, tie + costs[state].1));
+        if candidate.is_some() && (best.0.is_none() || candidate > best.0) {
+            best = (candidate, state as u8);
+        }
+    }
+    best
+}
+
+/// Globally aligns `x_len` against `y_len` tokens like [`global`], but scoring the opening
+/// of gap runs separately with Gotoh's three-state recurrence, and breaking ties between
+/// co-optimal alignments by adding `run_bonus` for every gap run opened.
+pub(crate) fn global_counting_runs<F>(
+    x_len: usize,
+    y_len: usize,
+    gaps: Gaps,
+    run_bonus: isize,
+    stats: &mut Stats,
+    score: F,
+) -> Alignment
+where
+    F: Fn(usize, usize) -> isize,
+{
+    let start = Instant::now();
+    let width = x_len + 1;
+    let mut back = vec![[0u8; 3]; width * (y_len + 1)];
+    let mut previous: Vec<[Value; 3]> = vec![[None; 3]; width];
+    let mut row: Vec<[Value; 3]> = vec![[None; 3]; width];
+
+    for y in 0..=y_len {
+        for x in 0..=x_len {
+            let at = y * width + x;
+            let mut cell = [None; 3];
+            if x == 0 && y == 0 {
+                cell[ALIGNED] = Some((0, 0));
+            }
+            if x > 0 && y > 0 {
+                let (value, from) = extend(&previous[x - 1], [(0, 0); 3]);
+                cell[ALIGNED] = value.map(|(s, tie)| (s + score(x - 1, y - 1), tie));
+                back[at][ALIGNED] = from;
+            }
+            if x > 0 {
+                let (open, gap) = (gaps.delete_open(y, y_len), gaps.delete(y, y_len));
+                let costs = [(open, run_bonus), (gap, 0), (open, run_bonus)];
+                (cell[DELETED], back[at][DELETED]) = extend(&row[x - 1], costs);
+            }
+            if y > 0 {
+                let (open, gap) = (gaps.insert_open(x, x_len), gaps.insert(x, x_len));
+                let costs = [(open, run_bonus), (open, run_bonus), (gap, 0)];
+                (cell[INSERTED], back[at][INSERTED]) = extend(&previous[x], costs);
+            }
+            row[x] = cell;
+        }
+        std::mem::swap(&mut previous, &mut row);
+    }
+    stats.cells += width * (y_len + 1);
+    stats.fill += start.elapsed();
+
+    let start = Instant::now();
+    let (best, mut state) = extend(&previous[x_len], [(0, 0); 3]);
+    let mut steps = vec![];
+    let mut cursor = Cursor { x: x_len, y: y_len };
+    while cursor.x > 0 || cursor.y > 0 {
+        let step = match state as usize {
+            ALIGNED => StepMask::ALIGN,
+            DELETED => StepMask::DELETE,
+            _ => StepMask::INSERT,
+        };
+        state = back[cursor.y * width + cursor.x][state as usize];
+        steps.push(step);
+        cursor.apply_backwards_step(step);
+    }
+    steps.reverse();
+    stats.traceback += start.elapsed();
+
+    let score = best.map_or(0, |(score, _)| score);
+    Alignment::new(Cursor { x: 0, y: 0 }, steps, score)
+}
+
+/// Finds the best local alignment of `x_len` against `y_len` tokens, scoring aligned pairs
+/// with `score` and every gap with `gap_score`. Returns an empty alignment if no pair scores
+/// above zero.
+pub(crate) fn local<F>(
+    x_len: usize,
+    y_len: usize,
+    gap_score: isize,
+    stats: &mut Stats,
+    score: F,
+) -> Alignment
+where
+    F: Fn(usize, usize) -> isize,
+{
+    let start = Instant::now();
+    let width = x_len + 1;
+    let mut masks = vec![StepMask::STOP; width * (y_len + 1)];
+    let mut previous = vec![0isize; width];
+    let mut row = vec![0isize; width];
+    let mut best = (0, Cursor { x: 0, y: 0 });
+
+    for y in 1..=y_len {
+        row[0] = 0;
+        for x in 1..=x_len {
+            let align = previous[x - 1] + score(x - 1, y - 1);
+            let delete = row[x - 1] + gap_score;
+            let insert = previous[x] + gap_score;
+            let cell = align.max(delete).max(insert);
+            if cell <= 0 {
+                row[x] = 0;
+                continue;
+            }
+            masks[y * width + x] = if delete == cell {
+                StepMask::DELETE
+            } else if insert == cell {
+                StepMask::INSERT
+            } else {
+                StepMask::ALIGN
+            };
+            row[x] = cell;
+            if cell > best.0 {
+                best = (cell, Cursor { x, y });
+            }
+        }
+        std::mem::swap(&mut previous, &mut row);
+    }
+    stats.cells += width * (y_len + 1);
+    stats.fill += start.elapsed();
+
+    let start = Instant::now();
+    let (score, mut cursor) = best;
+    let mut steps = vec![];
+    loop {
+        let mask = masks[cursor.y * width + cursor.x];
+        if mask == StepMask::STOP {
+            break;
+        }
+        steps.push(mask);
+        cursor.apply_backwards_step(mask);
+    }
+    steps.reverse();
+    stats.traceback += start.elapsed();
+
+    Alignment::new(cursor, steps, score)
+}
+
+/// Finds the score of the best local alignment of `x_len` against `y_len` tokens like
+/// [`local`], and the cell it ends at, without keeping what is needed to trace it back.
+pub(crate) fn local_max<F>(
+    x_len: usize,
+    y_len: usize,
+    gap_score: isize,
+    score: F,
+) -> (isize, Cursor)
+where
+    F: Fn(usize, usize) -> isize,
+{
+    let mut previous = vec![0isize; x_len + 1];
+    let mut row = vec![0isize; x_len + 1];
+    let mut best = (0, Cursor { x: 0, y: 0 });
+
+    for y in 1..=y_len {
+        row[0] = 0;
+        for x in 1..=x_len {
+            let cell = (previous[x - 1] + score(x - 1, y - 1))
+                .max(row[x - 1] + gap_score)
+                .max(previous[x] + gap_score)
+                .max(0);
+            row[x] = cell;
+            if cell > best.0 {
+                best = (cell, Cursor { x, y });
+            }
+        }
+        std::mem::swap(&mut previous, &mut row);
+    }
+    best
+}
+
+/// Counts the columns of the best local alignment of `x_len` against `y_len` tokens that
+/// `identical` holds for, and all its columns, as [`local`] would trace it back, carrying the
+/// counts of the path to each cell along the fill instead.
+pub(crate) fn local_identity<F, I>(
+    x_len: usize,
+    y_len: usize,
+    gap_score: isize,
+    score: F,
+    identical: I,
+) -> (usize, usize)
+where
+    F: Fn(usize, usize) -> isize,
+    I: Fn(usize, usize) -> bool,
+{
+    // Scores and `(matches, columns)` of the path to each cell of the previous and this row
+    let mut previous = vec![(0isize, (0usize, 0usize)); x_len + 1];
+    let mut row = vec![(0isize, (0usize, 0usize)); x_len + 1];
+    let mut best = (0, (0, 0));
+
+    for y in 0..y_len {
+        // Walking the rows with iterators rather than indices saves bounds checks.
+        let mut left = (0, (0, 0));
+        let above = previous.iter().zip(&previous[1..]);
+        for (x, (cell, (diagonal, up))) in row[1..].iter_mut().zip(above).enumerate() {
+            let align = diagonal.0 + score(x, y);
+            let delete = left.0 + gap_score;
+            let insert = up.0 + gap_score;
+            let value = align.max(delete).max(insert);
+            left = if value <= 0 {
+                (0, (0, 0))
+            } else {
+                // Ties are broken as in `local`, so the counts are of the path it traces back.
+                let (matches, columns) = if delete == value {
+                    left.1
+                } else if insert == value {
+                    up.1
+                } else {
+                    let (matches, columns) = diagonal.1;
+                    (matches + usize::from(identical(x, y)), columns)
+                };
+                (value, (matches, columns + 1))
+            };
+            *cell = left;
+            if value > best.0 {
+                best = left;
+            }
+        }
+        std::mem::swap(&mut previous, &mut row);
+    }
+    best.1
+}
+
+/// Like [`local`], but abandons cells scoring more than `x_drop` below the best score seen
+/// so far. Once a good enough hit is found, only its surroundings are explored, so the
+/// result may miss better alignments elsewhere.
+pub(crate) fn local_x_drop<F>(
+    x_len: usize,
+    y_len: usize,
+    gap_score: isize,
+    x_drop: isize,
+    stats: &mut Stats,
+    score: F,
+) -> Alignment
+where
+    F: Fn(usize, usize) -> isize,
+{
+    let start = Instant::now();
+    let mut masks: Vec<(usize, Vec<StepMask>)> = vec![(0, vec![StepMask::STOP; x_len + 1])];
+    let mut previous: (usize, Vec<Option<isize>>) = (0, vec![Some(0); x_len + 1]);
+    let mut best = (0, Cursor { x: 0, y: 0 });
+
+    for y in 1..=y_len {
+        // Fresh alignments start at zero, which is only worth exploring until a hit scores
+        // more than `x_drop`.
+        let fresh = best.0 <= x_drop;
+        let (previous_lo, ref previous_values) = previous;
+        let previous_hi = previous_lo + previous_values.len();
+        let above = |x: usize| -> Option<isize> {
+            if x < previous_lo {
+                return None;
+            }
+            previous_values.get(x - previous_lo).copied().flatten()
+        };
+
+        let lo = if fresh { 0 } else { previous_lo };
+        let mut values: Vec<Option<isize>> = vec![];
+        let mut row_masks = vec![];
+        for x in lo..=x_len {
+            let align = if x > 0 {
+                above(x - 1).map(|s| s + score(x - 1, y - 1))
+            } else {
+                None
+            };
+            let delete = match x.checked_sub(lo + 1) {
+                Some(i) => values[i].map(|s: isize| s + gap_score),
+                None => None,
+            };
+            let insert = above(x).map(|s| s + gap_score);
+            let candidate = [delete, insert, align].into_iter().flatten().max();
+
+            let (value, mask) = match candidate {
+                Some(value) if value > 0 || !fresh => {
+                    let mask = if delete == candidate {
+                        StepMask::DELETE
+                    } else if insert == candidate {
+                        StepMask::INSERT
+                    } else {
+                        StepMask::ALIGN
+                    };
+                    (Some(value), mask)
+                }
+                _ if fresh => (Some(0), StepMask::STOP),
+                _ => (None, StepMask::STOP),
+            };
+            let value = value.filter(|value| *value >= best.0 - x_drop);
+            if let Some(value) = value {
+                if value > best.0 {
+                    best = (value, Cursor { x, y });
+                }
+            }
+            values.push(value);
+            row_masks.push(mask);
+            stats.cells += 1;
+
+            if value.is_none() && !fresh && x > previous_hi {
+                break;
+            }
+        }
+
+        let first = values.iter().position(Option::is_some);
+        let last = values.iter().rposition(Option::is_some);
+        let (first, last) = match (first, last) {
+            (Some(first), Some(last)) => (first, last),
+            _ => break,
+        };
+        masks.push((lo + first, row_masks[first..=last].to_vec()));
+        previous = (lo + first, values[first..=last].to_vec());
+    }
+    stats.fill += start.elapsed();
+
+    let start = Instant::now();
+    let (score, mut cursor) = best;
+    let mut steps = vec![];
+    loop {
+        let (lo, ref row) = masks[cursor.y];
+        let mask = row[cursor.x - lo];
+        if mask == StepMask::STOP {
+            break;
+        }
+        steps.push(mask);
+        cursor.apply_backwards_step(mask);
+    }
+    steps.reverse();
+    stats.traceback += start.elapsed();
+
+    Alignment::new(cursor, steps, score)
+}
+
+/// Globally aligns `x_len` against `y_len` tokens like [`global`], using at most `max_gaps`
+/// gapped tokens. Returns `None` if the lengths differ by more than `max_gaps`.
+pub(crate) fn global_max_gaps<F>(
+    x_len: usize,
+    y_len: usize,
+    gaps: Gaps,
+    max_gaps: usize,
+    stats: &mut Stats,
+    score: F,
+) -> Option<Alignment>
+where
+    F: Fn(usize, usize) -> isize,
+{
+    if x_len.abs_diff(y_len) > max_gaps {
+        return None;
+    }
+    // No alignment has more gapped tokens than both sequences together.
+    let max_gaps = max_gaps.min(x_len + y_len);
+
+    let start = Instant::now();
+    let width = x_len + 1;
+    let layer = width * (y_len + 1);
+    let at = |k: usize, x: usize, y: usize| k * layer + y * width + x;
+    let mut values: Vec<Option<isize>> = vec![None; layer * (max_gaps + 1)];
+    let mut masks = vec![StepMask::STOP; layer * (max_gaps + 1)];
+
+    for k in 0..=max_gaps {
+        for y in 0..=y_len {
+            // Cells further off the diagonal than `k` cannot be reached with `k` gaps.
+            let lo = y.saturating_sub(k);
+            let hi = (y + k).min(x_len);
+            for x in lo..=hi {
+                if x == 0 && y == 0 {
+                    values[at(k, x, y)] = if k == 0 { Some(0) } else { None };
+                    continue;
+                }
+                let align = if x > 0 && y > 0 {
+                    values[at(k, x - 1, y - 1)].map(|s| s + score(x - 1, y - 1))
+                } else {
+                    None
+                };
+                let delete = if x > 0 && k > 0 {
+                    values[at(k - 1, x - 1, y)].map(|s| s + gaps.delete(y, y_len))
+                } else {
+                    None
+                };
+                let insert = if y > 0 && k > 0 {
+                    values[at(k - 1, x, y - 1)].map(|s| s + gaps.insert(x, x_len))
+                } else {
+                    None
+                };
+
+                let best = [delete, insert, align].into_iter().flatten().max();
+                masks[at(k, x, y)] = if best.is_none() {
+                    StepMask::STOP
+                } else if delete == best {
+                    StepMask::DELETE
+                } else if insert == best {
+                    StepMask::INSERT
+                } else {
+                    StepMask::ALIGN
+                };
+                values[at(k, x, y)] = best;
+                stats.cells += 1;
+            }
+        }
+    }
+    stats.fill += start.elapsed();
+
+    let start = Instant::now();
+    let (mut k, score) = (0..=max_gaps)
+        .filter_map(|k| values[at(k, x_len, y_len)].map(|score| (k, score)))
+        .fold(
+            None,
+            |best: Option<(usize, isize)>, (k, score)| match best {
+                Some((_, best_score)) if best_score >= score => best,
+                _ => Some((k, score)),
+            },
+        )?;
+    let mut steps = vec![];
+    let mut cursor = Cursor { x: x_len, y: y_len };
+    while cursor.x > 0 || cursor.y > 0 {
+        let mask = masks[at(k, cursor.x, cursor.y)];
+        if mask != StepMask::ALIGN {
+            k -= 1;
+        }
+        steps.push(mask);
+        cursor.apply_backwards_step(mask);
+    }
+    steps.reverse();
+    stats.traceback += start.elapsed();
+
+    Some(Alignment::new(Cursor { x: 0, y: 0 }, steps, score))
+}
+
+/// Globally aligns `x_len` against `y_len` tokens like [`global`], without any run of more
+/// than `max_length` consecutive gaps in the same sequence. Returns `None` if no alignment
+/// satisfies that.
+pub(crate) fn global_max_gap_length<F>(
+    x_len: usize,
+    y_len: usize,
+    gaps: Gaps,
+    max_length: usize,
+    stats: &mut Stats,
+    score: F,
+) -> Option<Alignment>
+where
+    F: Fn(usize, usize) -> isize,
+{
+    // No run of gaps is longer than the sequences.
+    let max_length = max_length.min(x_len.max(y_len));
+    // State 0 ends in an aligned pair, state `length` in a run of `length` deletions and
+    // state `max_length + length` in a run of `length` insertions.
+    let states = 2 * max_length + 1;
+    let is_deletion = |state: usize| (1..=max_length).contains(&state);
+    let is_insertion = |state: usize| state > max_length;
+    // Deletions are preferred, then insertions, then alignments, like `seal`.
+    let order: Vec<usize> = (1..states).chain([0]).collect();
+
+    let start = Instant::now();
+    let width = x_len + 1;
+    let at = |x: usize, y: usize| (y * width + x) * states;
+    let mut values: Vec<Option<isize>> = vec![None; width * (y_len + 1) * states];
+    let mut back = vec![0usize; width * (y_len + 1) * states];
+    values[at(0, 0)] = Some(0);
+
+    let best_of = |values: &[Option<isize>], cell: usize, allowed: &dyn Fn(usize) -> bool| {
+        let mut best = (None, 0);
+        for &state in order.iter().filter(|state| allowed(**state)) {
+            let value = values[cell + state];
+            if value.is_some() && (best.0.is_none() || value > best.0) {
+                best = (value, state);
+            }
+        }
+        best
+    };
+
+    for y in 0..=y_len {
+        for x in 0..=x_len {
+            let cell = at(x, y);
+            if x > 0 && y > 0 {
+                let (best, from) = best_of(&values, at(x - 1, y - 1), &|_| true);
+                values[cell] = best.map(|s| s + score(x - 1, y - 1));
+                back[cell] = from;
+            }
+            if x > 0 && max_length > 0 {
+                let gap = gaps.delete(y, y_len);
+                let (best, from) = best_of(&values, at(x - 1, y), &|state| !is_deletion(state));
+                values[cell + 1] = best.map(|s| s + gap);
+                back[cell + 1] = from;
+                for state in 2..=max_length {
+                    values[cell + state] = values[at(x - 1, y) + state - 1].map(|s| s + gap);
+                    back[cell + state] = state - 1;
+                }
+            }
+            if y > 0 && max_length > 0 {
+                let gap = gaps.insert(x, x_len);
+                let (best, from) = best_of(&values, at(x, y - 1), &|state| !is_insertion(state));
+                values[cell + max_length + 1] = best.map(|s| s + gap);
+                back[cell + max_length + 1] = from;
+                for state in max_length + 2..states {
+                    values[cell + state] = values[at(x, y - 1) + state - 1].map(|s| s + gap);
+                    back[cell + state] = state - 1;
+                }
+            }
+        }
+    }
+    stats.cells += width * (y_len + 1);
+    stats.fill += start.elapsed();
+
+    let start = Instant::now();
+    let (score, mut state) = best_of(&values, at(x_len, y_len), &|_| true);
+    let score = score?;
+    let mut steps = vec![];
+    let mut cursor = Cursor { x: x_len, y: y_len };
+    while cursor.x > 0 || cursor.y > 0 {
+        let step = if is_deletion(state) {
+            StepMask::DELETE
+        } else if is_insertion(state) {
+            StepMask::INSERT
+        } else {
+            StepMask::ALIGN
+        };
+        state = back[at(cursor.x, cursor.y) + state];
+        steps.push(step);
+        cursor.apply_backwards_step(step);
+    }
+    steps.reverse();
+    stats.traceback += start.elapsed();
+
+    Some(Alignment::new(Cursor { x: 0, y: 0 }, steps, score))
+}
+
+/// A global alignment of `y` tokens to a path through a partial order graph, as columns of
+/// the node and the token of `y` in each, `None` at gaps.
+pub(crate) struct PartialOrderAlignment {
+    pub score: isize,
+    pub columns: Vec<(Option<usize>, Option<usize>)>,
+}
+
+/// Aligns `y_len` tokens against the best path through a graph whose nodes are numbered in
+/// topological order, each listing its `predecessors`, as partial order alignment does. The
+/// path starts at a node without predecessors and ends at one without successors. Aligned
+/// pairs score `score(node, y)`, node and token gaps `gap`, and ties are broken like `seal`.
+pub(crate) fn partial_order<F>(
+    predecessors: &[Vec<usize>],
+    y_len: usize,
+    gap: isize,
+    stats: &mut Stats,
+    score: F,
+) -> PartialOrderAlignment
+where
+    F: Fn(usize, usize) -> isize,
+{
+    let start = Instant::now();
+    let width = y_len + 1;
+    let mut values = vec![0isize; predecessors.len() * width];
+    // The step into each cell, and the node it comes from, `None` being the virtual start
+    // before every node without predecessors.
+    let mut back = vec![(StepMask::STOP, None); predecessors.len() * width];
+    let value = |values: &[isize], node: Option<usize>, y: usize| match node {
+        Some(node) => values[node * width + y],
+        None => y as isize * gap,
+    };
+
+    for (node, node_predecessors) in predecessors.iter().enumerate() {
+        let sources: Vec<Option<usize>> = if node_predecessors.is_empty() {
+            vec![None]
+        } else {
+            node_predecessors.iter().copied().map(Some).collect()
+        };
+        for y in 0..width {
+            let mut best: Option<(isize, (StepMask, Option<usize>))> = None;
+            let mut consider = |candidate: isize, step: (StepMask, Option<usize>)| {
+                if best.is_none_or(|(value, _)| candidate > value) {
+                    best = Some((candidate, step));
+                }
+            };
+            for &source in &sources {
+                consider(value(&values, source, y) + gap, (StepMask::DELETE, source));
+            }
+            if y > 0 {
+                consider(
+                    values[node * width + y - 1] + gap,
+                    (StepMask::INSERT, Some(node)),
+                );
+                for &source in &sources {
+                    consider(
+                        value(&values, source, y - 1) + score(node, y - 1),
+                        (StepMask::ALIGN, source),
+                    );
+                }
+            }
+            let (cell, step) = best.expect("every cell has a deletion candidate");
+            values[node * width + y] = cell;
+            back[node * width + y] = step;
+        }
+    }
+    stats.cells += predecessors.len() * width;
+    stats.fill += start.elapsed();
+
+    let start = Instant::now();
+    let mut is_sink = vec![true; predecessors.len()];
+    for node_predecessors in predecessors {
+        for &predecessor in node_predecessors {
+            is_sink[predecessor] = false;
+        }
+    }
+    let end = (0..predecessors.len()).filter(|node| is_sink[*node]).fold(
+        None,
+        |best: Option<usize>, node| match best {
+            Some(best)
+                if value(&values, Some(best), y_len) >= value(&values, Some(node), y_len) =>
+            {
+                Some(best)
+            }
+            _ => Some(node),
+        },
+    );
+    let score = value(&values, end, y_len);
+
+    let mut columns = vec![];
+    let (mut node, mut y) = (end, y_len);
+    while let Some(current) = node {
+        let (step, source) = back[current * width + y];
+        if step == StepMask::INSERT {
+            columns.push((None, Some(y - 1)));
+            y -= 1;
+        } else if step == StepMask::DELETE {
+            columns.push((Some(current), None));
+            node = source;
+        } else {
+            columns.push((Some(current), Some(y - 1)));
+            node = source;
+            y -= 1;
+        }
+    }
+    columns.extend((0..y).rev().map(|y| (None, Some(y))));
+    columns.reverse();
+    stats.traceback += start.elapsed();
+
+    PartialOrderAlignment { score, columns }
+}
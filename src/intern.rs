@@ -0,0 +1,37 @@
+//! Token interning, so alignment fills compare integer ids instead of strings.
+
+use std::collections::HashMap;
+
+/// Assigns each distinct token a dense `u32` id, in order of first appearance.
+#[derive(Default)]
+pub(crate) struct Vocabulary<'a> {
+    ids: HashMap<&'a str, u32>,
+}
+
+impl<'a> Vocabulary<'a> {
+    pub(crate) fn intern(&mut self, token: &'a str) -> u32 {
+        let next = self.ids.len() as u32;
+        *self.ids.entry(token).or_insert(next)
+    }
+
+    pub(crate) fn intern_all(&mut self, tokens: &[&'a str]) -> Vec<u32> {
+        tokens.iter().map(|token| self.intern(token)).collect()
+    }
+
+    pub(crate) fn get(&self, token: &str) -> Option<u32> {
+        self.ids.get(token).copied()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Returns the interned tokens, indexed by id.
+    pub(crate) fn tokens(&self) -> Vec<&'a str> {
+        let mut tokens = vec![""; self.ids.len()];
+        for (token, id) in self.ids.iter() {
+            tokens[*id as usize] = token;
+        }
+        tokens
+    }
+}
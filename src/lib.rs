@@ -1,22 +1,219 @@
-use pyo3::{exceptions, prelude::*};
-use seal::pair::{Alignment, AlignmentSet, InMemoryAlignmentMatrix, NeedlemanWunsch, Step};
-use std::collections::HashMap;
+use itertools::Itertools;
+use pyo3::{
+    basic::CompareOp,
+    exceptions,
+    prelude::*,
+    types::{PyBool, PyDict, PyIterator, PyString},
+};
+use seal::pair::{
+    Alignment, AlignmentSet, Cursor, InMemoryAlignmentMatrix, NeedlemanWunsch, Step, StepMask,
+};
+use std::cmp::Reverse;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BinaryHeap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::time::Instant;
+use unicode_normalization::UnicodeNormalization;
+
+mod dp;
+mod intern;
+mod matrices;
+mod random;
+mod seeds;
 
 type SimilarityMatrix<'a> = HashMap<(&'a str, &'a str), isize>;
 
+const GAP: &str = "-";
+
+/// Symbols standing in for the missing token of a gap column: `delete` in the row of `b`
+/// where `a` has a token deleted, `insert` in the row of `a` where `b` has one inserted
+#[derive(Clone)]
+struct GapSymbols {
+    delete: String,
+    insert: String,
+}
+
+impl Default for GapSymbols {
+    fn default() -> Self {
+        GapSymbols {
+            delete: String::from(GAP),
+            insert: String::from(GAP),
+        }
+    }
+}
+
 fn trace<'a, T: ToString + Copy>(
     x_seq: &'a Vec<T>,
     y_seq: &'a Vec<T>,
+    symbols: &'a GapSymbols,
     alignment: &'a Alignment,
 ) -> impl Iterator<Item = (String, String)> + 'a {
-    alignment.steps().map(move |step| match step {
+    alignment
+        .steps()
+        .map(move |step| column(x_seq, y_seq, symbols, step))
+}
+
+fn column<T: ToString>(
+    x_seq: &[T],
+    y_seq: &[T],
+    symbols: &GapSymbols,
+    step: Step,
+) -> (String, String) {
+    match step {
         Step::Align { x, y } => (x_seq[x].to_string(), y_seq[y].to_string()),
-        Step::Delete { x } => (x_seq[x].to_string(), String::from("-")),
-        Step::Insert { y } => (String::from("-"), y_seq[y].to_string()),
+        Step::Delete { x } => (x_seq[x].to_string(), symbols.delete.clone()),
+        Step::Insert { y } => (symbols.insert.clone(), y_seq[y].to_string()),
+    }
+}
+
+/// What an alignment column does: aligns a token of `a` with one of `b`, inserts a token of
+/// `b`, or deletes a token of `a`
+#[pyclass]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Align,
+    Insert,
+    Delete,
+}
+
+/// Iterates the columns of `steps` as their `Op` and the tokens of `x_seq` and `y_seq` in
+/// them, `None` at gaps
+fn ops<'a, T: AsRef<str>>(
+    x_seq: &'a [T],
+    y_seq: &'a [T],
+    steps: impl IntoIterator<Item = Step> + 'a,
+) -> impl Iterator<Item = (Op, Option<&'a str>, Option<&'a str>)> + 'a {
+    steps.into_iter().map(move |step| match step {
+        Step::Align { x, y } => (Op::Align, Some(x_seq[x].as_ref()), Some(y_seq[y].as_ref())),
+        Step::Insert { y } => (Op::Insert, None, Some(y_seq[y].as_ref())),
+        Step::Delete { x } => (Op::Delete, Some(x_seq[x].as_ref()), None),
     })
 }
 
+/// An alignment column, with the tokens of `a` and `b` in it, `None` at gaps
+#[pyclass]
+#[derive(Clone)]
+struct Column {
+    #[pyo3(get)]
+    op: Op,
+    #[pyo3(get)]
+    a: Option<String>,
+    #[pyo3(get)]
+    b: Option<String>,
+}
+
+#[pymethods]
+impl Column {
+    fn __repr__(&self) -> String {
+        let token = |token: &Option<String>| match token {
+            Some(token) => format!("{:?}", token),
+            None => String::from("None"),
+        };
+        format!(
+            "Column(op=Op.{:?}, a={}, b={})",
+            self.op,
+            token(&self.a),
+            token(&self.b)
+        )
+    }
+}
+
+/// Number of characters in the widest token of a column, and at least one
+fn column_width(x_seq: &[&str], y_seq: &[&str], step: Step) -> u32 {
+    let width = match step {
+        Step::Align { x, y } => x_seq[x].chars().count().max(y_seq[y].chars().count()),
+        Step::Delete { x } => x_seq[x].chars().count(),
+        Step::Insert { y } => y_seq[y].chars().count(),
+    };
+    width.max(1) as u32
+}
+
+/// Extracts the argument `name` as a sequence of str tokens, raising a `TypeError` naming it
+/// and what it got otherwise
+fn tokens<'a>(name: &str, sequence: &'a PyAny) -> PyResult<Vec<&'a str>> {
+    if sequence.is_instance_of::<PyString>()? {
+        return Err(exceptions::PyTypeError::new_err(format!(
+            "{} must be a list of str tokens, not a str; split it into tokens first",
+            name
+        )));
+    }
+    let items = sequence.iter().map_err(|_| {
+        exceptions::PyTypeError::new_err(format!(
+            "{} must be a list of str tokens, got {}",
+            name,
+            type_name(sequence)
+        ))
+    })?;
+    items
+        .enumerate()
+        .map(|(i, item)| {
+            let item = item?;
+            item.downcast::<PyString>()
+                .map_err(|_| {
+                    exceptions::PyTypeError::new_err(format!(
+                        "{}[{}] must be a str token, got {}",
+                        name,
+                        i,
+                        type_name(item)
+                    ))
+                })?
+                .to_str()
+        })
+        .collect()
+}
+
+/// Extracts the argument `name` as a list of token sequences, like [`tokens`]
+fn token_lists<'a>(name: &str, sequences: &'a PyAny) -> PyResult<Vec<Vec<&'a str>>> {
+    if sequences.is_instance_of::<PyString>()? {
+        return Err(exceptions::PyTypeError::new_err(format!(
+            "{} must be a list of lists of str tokens, not a str",
+            name
+        )));
+    }
+    let items = sequences.iter().map_err(|_| {
+        exceptions::PyTypeError::new_err(format!(
+            "{} must be a list of lists of str tokens, got {}",
+            name,
+            type_name(sequences)
+        ))
+    })?;
+    items
+        .enumerate()
+        .map(|(i, item)| tokens(&format!("{}[{}]", name, i), item?))
+        .collect()
+}
+
+/// Extracts the argument `name` as a sequence of `(token, weight)` pairs, like [`tokens`]
+fn weighted_tokens<'a>(name: &str, sequence: &'a PyAny) -> PyResult<Vec<(&'a str, f64)>> {
+    let items = sequence.iter().map_err(|_| {
+        exceptions::PyTypeError::new_err(format!(
+            "{} must be a list of (str, float) pairs, got {}",
+            name,
+            type_name(sequence)
+        ))
+    })?;
+    items
+        .enumerate()
+        .map(|(i, item)| {
+            let item = item?;
+            item.extract().map_err(|_| {
+                exceptions::PyTypeError::new_err(format!(
+                    "{}[{}] must be a (str, float) pair, got {}",
+                    name,
+                    i,
+                    type_name(item)
+                ))
+            })
+        })
+        .collect()
+}
+
+fn type_name(value: &PyAny) -> &str {
+    value.get_type().name().unwrap_or("object")
+}
+
 #[pyclass]
+#[derive(Clone)]
 struct AlignmentResult {
     #[pyo3(get)]
     alignments: Vec<(String, String)>,
@@ -24,102 +221,5790 @@ struct AlignmentResult {
     alignment_score: isize,
     #[pyo3(get)]
     similarity_score: f64,
+    x_to_y: Vec<Option<usize>>,
+    #[pyo3(get)]
+    runs: Vec<(String, usize)>,
+    x_start: usize,
+    #[pyo3(get)]
+    x_end: usize,
+    y_start: usize,
+    #[pyo3(get)]
+    y_end: usize,
+    #[pyo3(get)]
+    diagnostics: Option<AlignmentDiagnostics>,
+    #[pyo3(get)]
+    truncated: bool,
+    #[pyo3(get)]
+    num_matches: usize,
+    #[pyo3(get)]
+    num_mismatches: usize,
+    #[pyo3(get)]
+    num_gaps: usize,
+    /// Fraction of matches among aligned columns and gap runs, counting each run as one
+    #[pyo3(get)]
+    gap_compressed_identity: f64,
+    /// Number of alignment matrix cells filled to find the alignment
+    #[pyo3(get)]
+    cells_evaluated: usize,
+    /// Algorithm that filled the alignment matrix, if one was filled
+    #[pyo3(get)]
+    algorithm: Option<&'static str>,
+    #[pyo3(get)]
+    z_score: Option<f64>,
+    #[pyo3(get)]
+    p_value: Option<f64>,
+    x_seq: Vec<String>,
+    y_seq: Vec<String>,
+    steps: Vec<Step>,
+    /// Whether each column aligns tokens the scorer matches, identical or not
+    matched: Vec<bool>,
+    column_scores: Vec<isize>,
+    gap_symbols: GapSymbols,
+    /// Lowest and highest score any alignment of the sequences could have
+    score_bounds: (isize, isize),
+    /// Whether positions are reported counting from 1, rather than from 0
+    #[pyo3(get)]
+    one_based: bool,
+    /// Whether the columns follow one another through both sequences, which they may not
+    /// after `filter_columns`
+    #[pyo3(get)]
+    contiguous: bool,
 }
 
-struct Scorer<'a> {
-    matrix: &'a SimilarityMatrix<'a>,
-    match_score: isize,
-    mismatch_score: isize,
-}
+impl AlignmentResult {
+    fn from_alignment(
+        x_seq: &Vec<&str>,
+        y_seq: &Vec<&str>,
+        scorer: &Scorer,
+        alignment: &Alignment,
+    ) -> AlignmentResult {
+        let start = *alignment.origin();
+        let end = alignment.steps().fold(start, |mut cursor, step| {
+            cursor.apply_forwards_step(step.mask());
+            cursor
+        });
+        let spans = (start.x, start.y, end.x, end.y) == (0, 0, x_seq.len(), y_seq.len());
+        let mut result = AlignmentResult {
+            alignments: trace(x_seq, y_seq, &GapSymbols::default(), alignment).collect(),
+            alignment_score: alignment.score(),
+            similarity_score: scorer.similarity_score(x_seq, y_seq, alignment),
+            x_to_y: index_map(alignment, x_seq.len()),
+            runs: vec![],
+            x_start: start.x,
+            x_end: end.x,
+            y_start: start.y,
+            y_end: end.y,
+            diagnostics: None,
+            truncated: false,
+            num_matches: 0,
+            num_mismatches: 0,
+            num_gaps: 0,
+            gap_compressed_identity: 0f64,
+            cells_evaluated: 0,
+            algorithm: None,
+            z_score: None,
+            p_value: None,
+            x_seq: x_seq.iter().map(|token| token.to_string()).collect(),
+            y_seq: y_seq.iter().map(|token| token.to_string()).collect(),
+            steps: alignment.steps().collect(),
+            matched: alignment
+                .steps()
+                .map(|step| match step {
+                    Step::Align { x, y } => scorer.matches(x_seq[x], y_seq[y]),
+                    _ => false,
+                })
+                .collect(),
+            column_scores: column_scores(x_seq, y_seq, scorer, alignment),
+            gap_symbols: GapSymbols::default(),
+            score_bounds: scorer.bounds(scorer.pair_bounds(), x_seq.len(), y_seq.len(), spans),
+            one_based: false,
+            contiguous: true,
+        };
+        result.count_columns();
+        result
+    }
 
-impl Scorer<'_> {
-    fn compare(&self, x: &str, y: &str) -> isize {
-        match self.matrix.get(&(x, y)) {
-            Some(score) => *score,
-            None => match self.matrix.get(&(y, x)) {
-                Some(score) => *score,
+    /// Whether the alignment starts and ends with both sequences, as global ones do
+    fn spans(&self) -> bool {
+        (self.x_start, self.y_start, self.x_end, self.y_end)
+            == (0, 0, self.x_seq.len(), self.y_seq.len())
+    }
 
-                None => {
-                    if x == y {
-                        self.match_score
-                    } else {
-                        self.mismatch_score
-                    }
+    /// Raises `ValueError` unless the columns are contiguous, as `operation` needs them to be
+    fn require_contiguous(&self, operation: &str) -> PyResult<()> {
+        if self.contiguous {
+            Ok(())
+        } else {
+            Err(exceptions::PyValueError::new_err(format!(
+                "{} needs contiguous columns, which filter_columns does not keep",
+                operation
+            )))
+        }
+    }
+
+    /// Sets `runs` and the counts of each kind of column from `steps`.
+    fn count_columns(&mut self) {
+        self.runs = self.collect_runs();
+        (self.num_matches, self.num_mismatches, self.num_gaps) = (0, 0, 0);
+        for column in 0..self.steps.len() {
+            match self.operation(column) {
+                "equal" => self.num_matches += 1,
+                "replace" => self.num_mismatches += 1,
+                _ => self.num_gaps += 1,
+            }
+        }
+        let gap_runs = self
+            .runs
+            .iter()
+            .filter(|(op, _)| op == "delete" || op == "insert")
+            .count();
+        let positions = self.num_matches + self.num_mismatches + gap_runs;
+        self.gap_compressed_identity = if positions > 0 {
+            self.num_matches as f64 / positions as f64
+        } else {
+            0f64
+        };
+    }
+
+    /// Rebuilds `alignments`, marking gaps with `gap_symbols`.
+    fn set_gap_symbols(&mut self, gap_symbols: GapSymbols) {
+        self.gap_symbols = gap_symbols;
+        self.alignments = self.steps.iter().map(|step| self.column(step)).collect();
+    }
+
+    fn column(&self, step: &Step) -> (String, String) {
+        column(&self.x_seq, &self.y_seq, &self.gap_symbols, step.clone())
+    }
+
+    /// Merges each run of gaps on the same side of `alignments` into one column, pairing as
+    /// many gap symbols with the run's tokens joined by a space. Other fields still describe
+    /// the uncollapsed alignment.
+    fn collapse_gaps(&mut self) {
+        self.alignments = self
+            .steps
+            .iter()
+            .map(|step| self.column(step))
+            .zip(self.steps.iter().map(|step| step.mask()))
+            .group_by(|(_, mask)| *mask)
+            .into_iter()
+            .flat_map(|(mask, columns)| {
+                let columns: Vec<(String, String)> = columns.map(|(column, _)| column).collect();
+                if mask == StepMask::ALIGN {
+                    return columns;
                 }
-            },
+                let gaps = if mask == StepMask::DELETE {
+                    self.gap_symbols.delete.repeat(columns.len())
+                } else {
+                    self.gap_symbols.insert.repeat(columns.len())
+                };
+                let tokens = columns
+                    .into_iter()
+                    .map(|(x, y)| if mask == StepMask::DELETE { x } else { y })
+                    .join(" ");
+                if mask == StepMask::DELETE {
+                    vec![(tokens, gaps)]
+                } else {
+                    vec![(gaps, tokens)]
+                }
+            })
+            .collect();
+    }
+
+    /// Re-expands an alignment of collapsed runs of `x_runs` and `y_runs` tokens to the tokens
+    /// of `x_seq` and `y_seq` they were collapsed from, with the tokens an aligned run has
+    /// beyond the other as gaps, rescoring the columns with `scorer` on `classes`, the classes
+    /// of the tokens of `x_seq` and `y_seq`. Scores are kept.
+    fn expand_runs(
+        self,
+        x_seq: &Vec<&str>,
+        y_seq: &Vec<&str>,
+        (x_classes, y_classes): &(Vec<&str>, Vec<&str>),
+        x_runs: &[usize],
+        y_runs: &[usize],
+        scorer: &Scorer,
+    ) -> AlignmentResult {
+        let mut masks = vec![];
+        for step in self.steps.iter() {
+            let (x_len, y_len) = match *step {
+                Step::Align { x, y } => (x_runs[x], y_runs[y]),
+                Step::Delete { x } => (x_runs[x], 0),
+                Step::Insert { y } => (0, y_runs[y]),
+            };
+            let aligned = x_len.min(y_len);
+            masks.extend(std::iter::repeat_n(StepMask::ALIGN, aligned));
+            masks.extend(std::iter::repeat_n(StepMask::DELETE, x_len - aligned));
+            masks.extend(std::iter::repeat_n(StepMask::INSERT, y_len - aligned));
+        }
+        let origin = Cursor {
+            x: x_runs[..self.x_start].iter().sum(),
+            y: y_runs[..self.y_start].iter().sum(),
+        };
+        let alignment = Alignment::new(origin, masks, self.alignment_score);
+        AlignmentResult {
+            similarity_score: self.similarity_score,
+            diagnostics: self.diagnostics,
+            cells_evaluated: self.cells_evaluated,
+            algorithm: self.algorithm,
+            z_score: self.z_score,
+            p_value: self.p_value,
+            score_bounds: self.score_bounds,
+            column_scores: column_scores(x_classes, y_classes, scorer, &alignment),
+            ..AlignmentResult::from_alignment(x_seq, y_seq, scorer, &alignment)
         }
     }
 
-    fn similarity_score(&self, x_seq: &Vec<&str>, y_seq: &Vec<&str>, alignment: &Alignment) -> f64 {
-        let (dis_correct, num_correct): (i32, u32) =
-            alignment.steps().fold((0, 0), |(dc, nc), step| match step {
-                Step::Align { x, y } => {
-                    if x_seq[x] == y_seq[y] {
-                        (dc + self.compare(&x_seq[x], &y_seq[y]) as i32, nc + 1)
+    /// Keeps only the first and last columns of `alignments`, `max_columns` in all. Other
+    /// fields still describe the whole alignment.
+    fn truncate(&mut self, max_columns: usize) {
+        let len = self.alignments.len();
+        if len <= max_columns {
+            return;
+        }
+        let head = max_columns.div_ceil(2);
+        self.alignments.drain(head..len - (max_columns - head));
+        self.truncated = true;
+    }
+
+    fn collect_runs(&self) -> Vec<(String, usize)> {
+        (0..self.steps.len())
+            .map(|column| match self.operation(column) {
+                "equal" => "match",
+                "replace" => "mismatch",
+                op => op,
+            })
+            .dedup_with_count()
+            .map(|(count, op)| (op.to_string(), count))
+            .collect()
+    }
+
+    /// What reported positions add to indices
+    fn offset(&self) -> usize {
+        usize::from(self.one_based)
+    }
+
+    /// Converts a reported position to an index, raising an `IndexError` for position 0 when
+    /// positions count from 1
+    fn index(&self, name: &str, position: usize) -> PyResult<usize> {
+        position.checked_sub(self.offset()).ok_or_else(|| {
+            exceptions::PyIndexError::new_err(format!("{} {} is not a position", name, position))
+        })
+    }
+
+    /// What column `column` does, counting aligned tokens as equal if the scorer matched them
+    fn operation(&self, column: usize) -> &'static str {
+        match self.steps[column] {
+            Step::Align { .. } if self.matched[column] => "equal",
+            Step::Align { .. } => "replace",
+            Step::Delete { .. } => "delete",
+            Step::Insert { .. } => "insert",
+        }
+    }
+}
+
+#[pymethods]
+impl AlignmentResult {
+    /// Builds a result from its `alignments` columns, with `-` marking gaps, and its scores
+    ///
+    /// Everything else is derived from the columns. Their individual scores are unknown, so
+    /// `score_breakdown` reports nothing. `max_possible_score` and `min_possible_score` are
+    /// those of aligning with `match_score`, `mismatch_score` and `gap_score`.
+    #[new]
+    #[args(match_score = 1, mismatch_score = -1, gap_score = -1)]
+    fn new(
+        alignments: Vec<(&str, &str)>,
+        alignment_score: isize,
+        similarity_score: f64,
+        match_score: isize,
+        mismatch_score: isize,
+        gap_score: isize,
+    ) -> PyResult<Self> {
+        let (aligned_a, aligned_b): (Vec<&str>, Vec<&str>) = alignments.into_iter().unzip();
+        let (a, b, masks) = ungap(&aligned_a, &aligned_b, GAP)?;
+        let masks_len = masks.len();
+        let alignment = Alignment::new(Cursor { x: 0, y: 0 }, masks, alignment_score);
+        let matrix = SimilarityMatrix::new();
+        let scorer = Scorer::new(
+            &matrix,
+            match_score,
+            mismatch_score,
+            dp::Gaps::linear(gap_score),
+            a.len(),
+            b.len(),
+        );
+        Ok(AlignmentResult {
+            similarity_score,
+            column_scores: vec![0; masks_len],
+            ..AlignmentResult::from_alignment(&a, &b, &scorer, &alignment)
+        })
+    }
+
+    /// Orders results by `alignment_score`, then by `similarity_score`
+    fn __richcmp__(&self, other: PyRef<AlignmentResult>, op: CompareOp) -> bool {
+        let ordering = self
+            .alignment_score
+            .cmp(&other.alignment_score)
+            .then(self.similarity_score.total_cmp(&other.similarity_score));
+        match op {
+            CompareOp::Lt => ordering.is_lt(),
+            CompareOp::Le => ordering.is_le(),
+            CompareOp::Eq => ordering.is_eq(),
+            CompareOp::Ne => ordering.is_ne(),
+            CompareOp::Gt => ordering.is_gt(),
+            CompareOp::Ge => ordering.is_ge(),
+        }
+    }
+
+    fn __hash__(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        (self.alignment_score, self.similarity_score.to_bits()).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Position in `a` of the first aligned token
+    #[getter]
+    fn x_start(&self) -> usize {
+        self.x_start + self.offset()
+    }
+
+    /// Position in `b` of the first aligned token
+    #[getter]
+    fn y_start(&self) -> usize {
+        self.y_start + self.offset()
+    }
+
+    /// Position in `b` of the token aligned to each token of `a`, or `None` where it is gapped
+    #[getter]
+    fn x_to_y(&self) -> Vec<Option<usize>> {
+        self.x_to_y
+            .iter()
+            .map(|y| y.map(|y| y + self.offset()))
+            .collect()
+    }
+
+    /// Returns the index in `b` aligned to `x_index` in `a`, or `None` if it was gapped
+    fn map_position(&self, x_index: usize) -> PyResult<Option<usize>> {
+        match self.x_to_y.get(self.index("x_index", x_index)?) {
+            Some(y_index) => Ok(y_index.map(|y| y + self.offset())),
+            None => Err(exceptions::PyIndexError::new_err(format!(
+                "x_index {} out of range for sequence of length {}",
+                x_index,
+                self.x_to_y.len()
+            ))),
+        }
+    }
+
+    /// Returns the half-open range in `b` spanned by `a[x_start..x_end]`, or `None` if it is
+    /// entirely gapped. With `one_based`, both ranges include their ends instead.
+    fn map_range(&self, x_start: usize, x_end: usize) -> PyResult<Option<(usize, usize)>> {
+        let start = self.index("x_start", x_start)?;
+        if start > x_end || x_end > self.x_to_y.len() {
+            return Err(exceptions::PyIndexError::new_err(format!(
+                "range {}..{} out of range for sequence of length {}",
+                x_start,
+                x_end,
+                self.x_to_y.len()
+            )));
+        }
+
+        let mut mapped = self.x_to_y[start..x_end].iter().flatten();
+        Ok(mapped.next().map(|first| {
+            let last = mapped.last().unwrap_or(first);
+            (first + self.offset(), last + 1)
+        }))
+    }
+
+    /// Returns the fractions of `a` and of `b` aligned to a token rather than a gap
+    fn coverage(&self) -> (f64, f64) {
+        let aligned = self
+            .steps
+            .iter()
+            .filter(|step| matches!(step, Step::Align { .. }))
+            .count() as f64;
+        let fraction = |len: usize| if len == 0 { 0f64 } else { aligned / len as f64 };
+        (fraction(self.x_seq.len()), fraction(self.y_seq.len()))
+    }
+
+    /// Returns the highest score an alignment of the two sequences could have with the
+    /// scores it was aligned with, had every pair of tokens their best score
+    fn max_possible_score(&self) -> isize {
+        self.score_bounds.1
+    }
+
+    /// Returns the lowest score an alignment of the two sequences could have with the scores
+    /// it was aligned with, had every pair of tokens their worst score
+    fn min_possible_score(&self) -> isize {
+        self.score_bounds.0
+    }
+
+    /// Returns the percentage of columns aligning identical tokens
+    fn percent_identity(&self) -> f64 {
+        if self.steps.is_empty() {
+            return 0f64;
+        }
+        100f64 * self.num_matches as f64 / self.steps.len() as f64
+    }
+
+    /// Returns `(op, from, to)` edit operations, where `op` is one of `"equal"`, `"replace"`,
+    /// `"insert"` or `"delete"` and consecutive operations of the same kind are collapsed
+    /// into one, joining their tokens with a space
+    fn edit_script(&self) -> Vec<(String, Option<String>, Option<String>)> {
+        self.steps
+            .iter()
+            .enumerate()
+            .group_by(|(column, _)| self.operation(*column))
+            .into_iter()
+            .map(|(op, steps)| {
+                let (xs, ys): (Vec<_>, Vec<_>) = steps
+                    .map(|(_, step)| match *step {
+                        Step::Align { x, y } => (Some(&self.x_seq[x]), Some(&self.y_seq[y])),
+                        Step::Delete { x } => (Some(&self.x_seq[x]), None),
+                        Step::Insert { y } => (None, Some(&self.y_seq[y])),
+                    })
+                    .unzip();
+                let join = |tokens: Vec<Option<&String>>| -> Option<String> {
+                    let tokens: Vec<&String> = tokens.into_iter().flatten().collect();
+                    if tokens.is_empty() {
+                        None
                     } else {
-                        (dc, nc)
+                        Some(tokens.into_iter().join(" "))
                     }
+                };
+                (op.to_string(), join(xs), join(ys))
+            })
+            .collect()
+    }
+
+    /// Returns the columns of the alignment, each with its `Op` and its tokens of `a` and
+    /// `b`, `None` rather than a gap symbol where there is none
+    fn columns(&self) -> Vec<Column> {
+        ops(&self.x_seq, &self.y_seq, self.steps.iter().cloned())
+            .map(|(op, a, b)| Column {
+                op,
+                a: a.map(str::to_string),
+                b: b.map(str::to_string),
+            })
+            .collect()
+    }
+
+    /// Returns the columns of the alignment as parallel lists, keyed `"x"` and `"y"` for the
+    /// tokens (or gap symbols) of `a` and `b`, `"op"` for the operation as in `edit_script`,
+    /// and `"x_index"` and `"y_index"` for the token indices, `None` at gaps, so that the
+    /// result can be loaded as a data frame
+    fn to_columns(&self, py: Python) -> HashMap<String, PyObject> {
+        let (xs, ys): (Vec<String>, Vec<String>) =
+            self.steps.iter().map(|step| self.column(step)).unzip();
+        let ops: Vec<&str> = (0..self.steps.len())
+            .map(|column| self.operation(column))
+            .collect();
+        let (x_indices, y_indices): (Vec<Option<usize>>, Vec<Option<usize>>) = self
+            .steps
+            .iter()
+            .map(|step| match *step {
+                Step::Align { x, y } => (Some(x + self.offset()), Some(y + self.offset())),
+                Step::Delete { x } => (Some(x + self.offset()), None),
+                Step::Insert { y } => (None, Some(y + self.offset())),
+            })
+            .unzip();
+        HashMap::from([
+            (String::from("x"), xs.into_py(py)),
+            (String::from("y"), ys.into_py(py)),
+            (String::from("op"), ops.into_py(py)),
+            (String::from("x_index"), x_indices.into_py(py)),
+            (String::from("y_index"), y_indices.into_py(py)),
+        ])
+    }
+
+    /// Returns the row of `a` in the alignment, with gaps where `b` has inserted tokens
+    fn gapped_x(&self) -> Vec<String> {
+        self.steps.iter().map(|step| self.column(step).0).collect()
+    }
+
+    /// Returns the row of `b` in the alignment, with gaps where `a` has deleted tokens
+    fn gapped_y(&self) -> Vec<String> {
+        self.steps.iter().map(|step| self.column(step).1).collect()
+    }
+
+    /// Returns the lengths of the unaligned start and end of `a`, the query
+    fn clips(&self) -> (usize, usize) {
+        (self.x_start, self.x_seq.len() - self.x_end)
+    }
+
+    /// Returns a SAM CIGAR string with `a` as the query and `b` as the reference, soft
+    /// clipping any unaligned start or end of `a`
+    fn cigar(&self) -> PyResult<String> {
+        self.require_contiguous("cigar")?;
+        let (leading, trailing) = self.clips();
+        let ops = self.steps.iter().map(|step| match step {
+            Step::Align { .. } => 'M',
+            Step::Delete { .. } => 'I',
+            Step::Insert { .. } => 'D',
+        });
+        Ok(std::iter::repeat_n('S', leading)
+            .chain(ops)
+            .chain(std::iter::repeat_n('S', trailing))
+            .dedup_with_count()
+            .map(|(count, op)| format!("{}{}", count, op))
+            .collect())
+    }
+
+    /// Returns `(a_start, b_start, length)` for each run of at least `min_length` consecutive
+    /// identical aligned tokens
+    fn matching_runs(&self, min_length: usize) -> PyResult<Vec<(usize, usize, usize)>> {
+        self.require_contiguous("matching_runs")?;
+        Ok(self
+            .steps
+            .iter()
+            .enumerate()
+            .group_by(|(column, _)| self.operation(*column) == "equal")
+            .into_iter()
+            .filter(|(equal, _)| *equal)
+            .filter_map(|(_, mut steps)| match steps.next() {
+                Some((_, Step::Align { x, y })) => {
+                    Some((x + self.offset(), y + self.offset(), 1 + steps.count()))
                 }
-                _ => (dc, nc),
-            });
+                _ => None,
+            })
+            .filter(|(_, _, length)| *length >= min_length)
+            .collect())
+    }
+
+    /// Transfers `labels`, one for each token of `a`, to the tokens of `b` they are aligned
+    /// to, returning one label for each token of `b`, `None` where it is not aligned with a
+    /// token of `a`
+    fn project_labels(&self, labels: Vec<Option<String>>) -> PyResult<Vec<Option<String>>> {
+        if labels.len() != self.x_seq.len() {
+            return Err(exceptions::PyValueError::new_err(format!(
+                "expected {} labels, one for each token of a, got {}",
+                self.x_seq.len(),
+                labels.len()
+            )));
+        }
+        let mut projected = vec![None; self.y_seq.len()];
+        for step in self.steps.iter() {
+            if let Step::Align { x, y } = *step {
+                projected[y] = labels[x].clone();
+            }
+        }
+        Ok(projected)
+    }
+
+    /// Returns `(column_index, a_token, b_token)` for each column that is not a pair of
+    /// identical tokens, with the gap symbol on the side of a gap
+    fn differences(&self) -> Vec<(usize, String, String)> {
+        self.steps
+            .iter()
+            .enumerate()
+            .filter(|(column, _)| self.operation(*column) != "equal")
+            .map(|(index, step)| {
+                let (x_token, y_token) = self.column(step);
+                (index, x_token, y_token)
+            })
+            .collect()
+    }
+
+    /// Returns the parts of `alignment_score` contributed by `"match"`, `"mismatch"` and
+    /// `"gap"` columns
+    fn score_breakdown(&self) -> HashMap<String, isize> {
+        let mut breakdown: HashMap<String, isize> = ["match", "mismatch", "gap"]
+            .into_iter()
+            .map(|kind| (kind.to_string(), 0))
+            .collect();
+        for (column, score) in self.column_scores.iter().enumerate() {
+            let kind = match self.operation(column) {
+                "equal" => "match",
+                "replace" => "mismatch",
+                _ => "gap",
+            };
+            *breakdown.get_mut(kind).unwrap() += score;
+        }
+        breakdown
+    }
+
+    /// Returns a copy of this alignment keeping only the columns scoring at least
+    /// `min_column_score`, and with `drop_gaps`, only aligned columns
+    ///
+    /// Scores and counts are recomputed from the remaining columns, with identical tokens
+    /// counting as correct for the similarity score. This trims the alignment after the fact,
+    /// so it need no longer be the best one, nor cover the sequences contiguously; positions
+    /// still refer to the original sequences. Unless every column is kept, the result is not
+    /// `contiguous`, and `cigar`, `matching_runs`, `rescore`, `multi_score`, `refine` and
+    /// `merge_via_common` raise `ValueError` for it.
+    #[args(drop_gaps = false)]
+    fn filter_columns(&self, min_column_score: isize, drop_gaps: bool) -> AlignmentResult {
+        let kept: Vec<usize> = (0..self.steps.len())
+            .filter(|&column| {
+                self.column_scores[column] >= min_column_score
+                    && (!drop_gaps || matches!(self.steps[column], Step::Align { .. }))
+            })
+            .collect();
+        let steps: Vec<Step> = kept
+            .iter()
+            .map(|&column| self.steps[column].clone())
+            .collect();
+        let column_scores: Vec<isize> = kept
+            .iter()
+            .map(|&column| self.column_scores[column])
+            .collect();
 
-        if num_correct == 0 {
-            return -1f64;
+        let mut result = AlignmentResult {
+            alignment_score: column_scores.iter().sum(),
+            x_to_y: vec![None; self.x_seq.len()],
+            column_scores,
+            diagnostics: None,
+            truncated: false,
+            z_score: None,
+            p_value: None,
+            contiguous: self.contiguous && steps.len() == self.steps.len(),
+            matched: kept.iter().map(|&column| self.matched[column]).collect(),
+            steps,
+            ..self.clone()
+        };
+        for step in result.steps.iter() {
+            if let Step::Align { x, y } = *step {
+                result.x_to_y[x] = Some(y);
+            }
         }
+        result.alignments = result
+            .steps
+            .iter()
+            .map(|step| result.column(step))
+            .collect();
+        result.count_columns();
+        let scores: HashMap<usize, isize> = result
+            .steps
+            .iter()
+            .zip(result.column_scores.iter())
+            .filter_map(|(step, score)| match *step {
+                Step::Align { x, .. } => Some((x, *score)),
+                _ => None,
+            })
+            .collect();
+        result.similarity_score = score_steps_similarity(
+            &result.steps,
+            result.alignment_score,
+            |x, y| {
+                if result.x_seq[x] == result.y_seq[y] {
+                    scores.get(&x).copied()
+                } else {
+                    None
+                }
+            },
+            |_| 1f64,
+        );
+        result
+    }
 
-        let dis = alignment.score() as i32;
+    /// Returns the score of this alignment under each of `param_sets`, in order
+    ///
+    /// Like `rescore`, the columns are kept as they are. Tokens are scored as they are, even
+    /// if a parameter set has a `token_class_fn`.
+    fn multi_score(&self, param_sets: Vec<PyRef<AlignmentParams>>) -> PyResult<Vec<isize>> {
+        self.require_contiguous("multi_score")?;
+        let x_seq: Vec<&str> = self.x_seq.iter().map(String::as_str).collect();
+        let y_seq: Vec<&str> = self.y_seq.iter().map(String::as_str).collect();
+        let origin = Cursor {
+            x: self.x_start,
+            y: self.y_start,
+        };
+        let alignment = Alignment::new(
+            origin,
+            self.steps.iter().map(|step| step.mask()).collect(),
+            0,
+        );
+        Ok(param_sets
+            .iter()
+            .map(|params| {
+                let matrix = params.matrix();
+                let scorer = params.scorer(&matrix, x_seq.len(), y_seq.len());
+                column_scores(&x_seq, &y_seq, &scorer, &alignment)
+                    .iter()
+                    .sum()
+            })
+            .collect())
+    }
 
-        let sim_align = match dis_correct {
-            0 => 0f64,
-            _ => f64::from(dis) / f64::from(dis_correct),
+    /// Returns a copy of this alignment scored with different parameters
+    ///
+    /// The columns are kept as they are, so the new scores are those of this alignment under
+    /// the new parameters, not of the best alignment under them.
+    #[args(match_score = 1, mismatch_score = -1, gap_score = -1)]
+    fn rescore(
+        &self,
+        match_score: isize,
+        mismatch_score: isize,
+        gap_score: isize,
+        similarity_matrix: Option<SimilarityMatrix>,
+    ) -> PyResult<AlignmentResult> {
+        self.require_contiguous("rescore")?;
+        let matrix = similarity_matrix.unwrap_or_default();
+        let scorer = Scorer::new(
+            &matrix,
+            match_score,
+            mismatch_score,
+            dp::Gaps::linear(gap_score),
+            self.x_seq.len(),
+            self.y_seq.len(),
+        );
+        let x_seq: Vec<&str> = self.x_seq.iter().map(String::as_str).collect();
+        let y_seq: Vec<&str> = self.y_seq.iter().map(String::as_str).collect();
+        let origin = Cursor {
+            x: self.x_start,
+            y: self.y_start,
         };
+        let masks: Vec<StepMask> = self.steps.iter().map(|step| step.mask()).collect();
+        let column_scores = column_scores(
+            &x_seq,
+            &y_seq,
+            &scorer,
+            &Alignment::new(origin, masks.clone(), 0),
+        );
+        let score = column_scores.iter().sum();
+        let alignment = Alignment::new(origin, masks, score);
+
+        Ok(AlignmentResult {
+            alignment_score: score,
+            similarity_score: scorer.similarity_score(&x_seq, &y_seq, &alignment),
+            diagnostics: None,
+            column_scores,
+            score_bounds: scorer.bounds(
+                scorer.pair_bounds(),
+                x_seq.len(),
+                y_seq.len(),
+                self.spans(),
+            ),
+            ..self.clone()
+        })
+    }
+}
+
+/// Scores each column of `alignment` as the fill did
+fn column_scores(
+    x_seq: &[&str],
+    y_seq: &[&str],
+    scorer: &Scorer,
+    alignment: &Alignment,
+) -> Vec<isize> {
+    let mut cursor = *alignment.origin();
+    let mut previous = StepMask::ALIGN;
+    alignment
+        .steps()
+        .map(|step| {
+            let mask = step.mask();
+            cursor.apply_forwards_step(mask);
+            let opens = mask != previous;
+            previous = mask;
+            match step {
+                Step::Align { x, y } => scorer.compare(x_seq[x], y_seq[y]),
+                Step::Delete { .. } if opens => scorer.gaps.delete_open(cursor.y, y_seq.len()),
+                Step::Delete { .. } => scorer.gaps.delete(cursor.y, y_seq.len()),
+                Step::Insert { .. } if opens => scorer.gaps.insert_open(cursor.x, x_seq.len()),
+                Step::Insert { .. } => scorer.gaps.insert(cursor.x, x_seq.len()),
+            }
+        })
+        .collect()
+}
 
-        let sim_significance = f64::from(num_correct) / f64::from(alignment.len() as i32);
+/// Work done computing an alignment
+#[pyclass]
+#[derive(Clone)]
+struct AlignmentDiagnostics {
+    #[pyo3(get)]
+    cells_filled: usize,
+    #[pyo3(get)]
+    fill_micros: u128,
+    #[pyo3(get)]
+    traceback_micros: u128,
+    /// Number of co-optimal alignments, if the fill counted them
+    #[pyo3(get)]
+    num_optimal_paths: Option<u64>,
+}
 
-        sim_align * sim_significance
+impl From<dp::Stats> for AlignmentDiagnostics {
+    fn from(stats: dp::Stats) -> Self {
+        AlignmentDiagnostics {
+            cells_filled: stats.cells,
+            fill_micros: stats.fill.as_micros(),
+            traceback_micros: stats.traceback.as_micros(),
+            num_optimal_paths: None,
+        }
     }
 }
 
-/// Finds alignment similarity between two sequences
-#[pyfunction(match_score=1, mismatch_score=-1, gap_score=-1)]
-fn align(
-    _py: Python,
-    a: Vec<&str>,
-    b: Vec<&str>,
+/// Turns an alignment of sequences of `x_len` and `y_len` tokens, reversed, into the same
+/// alignment of the sequences in order
+fn reverse_alignment(alignment: &Alignment, x_len: usize, y_len: usize) -> Alignment {
+    let masks: Vec<StepMask> = alignment.steps().map(|step| step.mask()).collect();
+    let end = masks.iter().fold(*alignment.origin(), |mut cursor, mask| {
+        cursor.apply_forwards_step(*mask);
+        cursor
+    });
+    Alignment::new(
+        Cursor {
+            x: x_len - end.x,
+            y: y_len - end.y,
+        },
+        masks.into_iter().rev().collect(),
+        alignment.score(),
+    )
+}
+
+fn index_map(alignment: &Alignment, x_len: usize) -> Vec<Option<usize>> {
+    let mut x_to_y = vec![None; x_len];
+    for step in alignment.steps() {
+        if let Step::Align { x, y } = step {
+            x_to_y[x] = Some(y);
+        }
+    }
+    x_to_y
+}
+
+struct Scorer<'a> {
+    matrix: &'a SimilarityMatrix<'a>,
     match_score: isize,
     mismatch_score: isize,
-    gap_score: isize,
-    similarity_matrix: Option<SimilarityMatrix>,
-) -> PyResult<AlignmentResult> {
-    let needleman_wunsch = NeedlemanWunsch::new(mismatch_score, gap_score, gap_score);
-    let scorer = Scorer {
-        matrix: &similarity_matrix.unwrap_or(HashMap::new()),
-        match_score,
-        mismatch_score,
-    };
+    gaps: dp::Gaps,
+    matrix_is_authoritative: bool,
+    forbidden_score: isize,
+    wildcards: Vec<(&'a str, &'a str, isize)>,
+    ambiguity: Option<&'a HashMap<String, Vec<String>>>,
+    forbid_mismatch: bool,
+    matrix_as_floor: bool,
+    idf: Option<&'a HashMap<String, f64>>,
+    unicode_normalize: bool,
+    correctness: Correctness,
+}
 
-    let alignment_set: Result<AlignmentSet<InMemoryAlignmentMatrix>, _> =
-        AlignmentSet::new(a.len(), b.len(), needleman_wunsch, |x, y| {
-            scorer.compare(a[x], b[y])
-        });
+/// Similarity matrix score marking a pair that must never be aligned
+const FORBIDDEN: isize = isize::MIN;
 
-    match alignment_set {
-        Ok(ref alignment_set) => {
-            let global_alignment = alignment_set.global_alignment();
-            Ok(AlignmentResult {
-                alignments: trace(&a, &b, &global_alignment).collect(),
-                alignment_score: global_alignment.score(),
-                similarity_score: scorer.similarity_score(&a, &b, &global_alignment),
+/// Picks a score for [`FORBIDDEN`] pairs that loses to any alignment avoiding them, yet
+/// can be summed along the longest possible alignment without overflowing.
+fn forbidden_score(x_len: usize, y_len: usize, scores: impl Iterator<Item = isize>) -> isize {
+    let columns = (x_len + y_len + 1) as isize;
+    let bound = scores
+        .filter(|score| *score != FORBIDDEN)
+        .map(|score| score.saturating_abs())
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let floor = isize::MIN / (columns + 1);
+    bound
+        .saturating_mul(2)
+        .saturating_mul(columns)
+        .saturating_add(1)
+        .saturating_neg()
+        .max(floor)
+}
+
+impl<'a> Scorer<'a> {
+    fn new(
+        matrix: &'a SimilarityMatrix<'a>,
+        match_score: isize,
+        mismatch_score: isize,
+        gaps: dp::Gaps,
+        x_len: usize,
+        y_len: usize,
+    ) -> Scorer<'a> {
+        let scores = [match_score, mismatch_score]
+            .into_iter()
+            .chain(gaps.scores());
+        let mut wildcards: Vec<(&str, &str, isize)> = matrix
+            .iter()
+            .filter(|((x, y), _)| x.ends_with('*') || y.ends_with('*'))
+            .map(|((x, y), score)| (*x, *y, *score))
+            .collect();
+        let specificity = |key: &str| key.strip_suffix('*').unwrap_or(key).len();
+        wildcards
+            .sort_by_key(|(x, y, _)| (std::cmp::Reverse(specificity(x) + specificity(y)), *x, *y));
+        Scorer {
+            matrix,
+            match_score,
+            mismatch_score,
+            gaps,
+            matrix_is_authoritative: false,
+            forbidden_score: forbidden_score(x_len, y_len, scores.chain(matrix.values().copied())),
+            wildcards,
+            ambiguity: None,
+            forbid_mismatch: false,
+            matrix_as_floor: false,
+            idf: None,
+            unicode_normalize: false,
+            correctness: Correctness::Identity,
+        }
+    }
+
+    /// Looks up `(x, y)` in the matrix, then `(y, x)`, then keys with a trailing `*` matching
+    /// any token with that prefix (in either order, longest prefixes first), before falling
+    /// back to `match_score`/`mismatch_score`. An authoritative matrix treats matching tokens
+    /// missing from both lookups as a mismatch. With `matrix_as_floor`, matrix entries
+    /// scoring below that fallback score the fallback instead. Pairs scored [`FORBIDDEN`] get
+    /// `forbidden_score` instead, as do all pairs that do not match with `forbid_mismatch`.
+    /// With `idf`, other scores are scaled by the mean weight of the two tokens and rounded.
+    fn compare(&self, x: &str, y: &str) -> isize {
+        let score = self.compare_unweighted(x, y);
+        match self.idf {
+            Some(idf) if score != self.forbidden_score => {
+                let weight = |token: &str| idf.get(token).copied().unwrap_or(1f64);
+                (score as f64 * (weight(x) + weight(y)) / 2f64).round() as isize
+            }
+            _ => score,
+        }
+    }
+
+    /// Weight of the tokens of a column in `idf`, any missing counting as 1, and averaged
+    /// for aligned pairs as in [`Scorer::compare`]
+    fn idf_weight(&self, x_seq: &[&str], y_seq: &[&str], step: Step) -> f64 {
+        let weight = |token: &str| {
+            self.idf
+                .and_then(|idf| idf.get(token).copied())
+                .unwrap_or(1f64)
+        };
+        match step {
+            Step::Align { x, y } => (weight(x_seq[x]) + weight(y_seq[y])) / 2f64,
+            Step::Delete { x } => weight(x_seq[x]),
+            Step::Insert { y } => weight(y_seq[y]),
+        }
+    }
+
+    fn compare_unweighted(&self, x: &str, y: &str) -> isize {
+        if self.forbid_mismatch && !self.matches(x, y) {
+            return self.forbidden_score;
+        }
+        let fallback = || {
+            if self.matches(x, y) && !self.matrix_is_authoritative {
+                self.match_score
+            } else {
+                self.mismatch_score
+            }
+        };
+        match self.lookup(x, y) {
+            Some(FORBIDDEN) => self.forbidden_score,
+            Some(score) if self.matrix_as_floor => score.max(fallback()),
+            Some(score) => score,
+            None => fallback(),
+        }
+    }
+
+    /// Whether `x` and `y` are identical, or canonically equivalent with `unicode_normalize`,
+    /// or either is in the other's ambiguity set.
+    fn matches(&self, x: &str, y: &str) -> bool {
+        let ambiguous = |token: &str, code: &str| {
+            self.ambiguity
+                .and_then(|ambiguity| ambiguity.get(code))
+                .is_some_and(|tokens| tokens.iter().any(|t| t == token))
+        };
+        x == y
+            || (self.unicode_normalize && x.nfc().eq(y.nfc()))
+            || ambiguous(x, y)
+            || ambiguous(y, x)
+    }
+
+    fn lookup(&self, x: &str, y: &str) -> Option<isize> {
+        self.matrix
+            .get(&(x, y))
+            .or_else(|| self.matrix.get(&(y, x)))
+            .copied()
+            .or_else(|| self.lookup_wildcard(x, y))
+    }
+
+    fn lookup_wildcard(&self, x: &str, y: &str) -> Option<isize> {
+        let matches = |pattern: &str, token: &str| match pattern.strip_suffix('*') {
+            Some(prefix) => token.starts_with(prefix),
+            None => pattern == token,
+        };
+        self.wildcards
+            .iter()
+            .find(|(x_pattern, y_pattern, _)| {
+                (matches(x_pattern, x) && matches(y_pattern, y))
+                    || (matches(x_pattern, y) && matches(y_pattern, x))
             })
+            .map(|(_, _, score)| *score)
+    }
+
+    /// Returns the lowest and highest scores an alignment of `x_len` against `y_len` tokens
+    /// could have, had every pair and every gap the worst or best score it can get, with pairs
+    /// scoring from `worst_pair` to `best_pair`. Unless the alignment `spans` both sequences,
+    /// as local ones need not, it may also align nothing at all, or only pairs.
+    fn bounds(
+        &self,
+        (worst_pair, best_pair): (isize, isize),
+        x_len: usize,
+        y_len: usize,
+        spans: bool,
+    ) -> (isize, isize) {
+        let (worst_gap, best_gap) = self
+            .gaps
+            .scores()
+            .minmax()
+            .into_option()
+            .expect("gaps are never empty");
+        let (pairs, tokens) = (x_len.min(y_len) as isize, (x_len + y_len) as isize);
+        // Scores are linear in the number of aligned pairs, so are extreme with all or none.
+        let bound =
+            |pair: isize, gap: isize| [pairs * pair + (tokens - 2 * pairs) * gap, tokens * gap];
+        let [aligned, gapped] = bound(worst_pair, worst_gap);
+        let lowest = aligned.min(gapped);
+        let [aligned, gapped] = bound(best_pair, best_gap);
+        let highest = aligned.max(gapped);
+        if spans {
+            (lowest, highest)
+        } else {
+            (
+                lowest.min(0).min(pairs * worst_pair),
+                highest.max(0).max(pairs * best_pair),
+            )
         }
-        Err(error) => Err(exceptions::PyValueError::new_err(error)),
+    }
+
+    /// Returns the lowest and highest scores any pair of tokens could get, short of
+    /// [`FORBIDDEN`] pairs, scaled by the lightest or heaviest `idf` weight
+    fn pair_bounds(&self) -> (isize, isize) {
+        let (worst, best) = [self.match_score, self.mismatch_score]
+            .into_iter()
+            .chain(
+                self.matrix
+                    .values()
+                    .copied()
+                    .filter(|score| *score != FORBIDDEN),
+            )
+            .minmax()
+            .into_option()
+            .expect("there are always match and mismatch scores");
+        let Some(idf) = self.idf else {
+            return (worst, best);
+        };
+        // Tokens missing from `idf` weigh 1, and pairs their mean, so within these weights.
+        let (lightest, heaviest) = idf
+            .values()
+            .copied()
+            .chain([1f64])
+            .minmax()
+            .into_option()
+            .expect("tokens missing from idf weigh 1");
+        let scaled = |score: isize| {
+            [lightest, heaviest].map(|weight| (score as f64 * weight).round() as isize)
+        };
+        let ([worst_light, worst_heavy], [best_light, best_heavy]) = (scaled(worst), scaled(best));
+        (worst_light.min(worst_heavy), best_light.max(best_heavy))
+    }
+
+    fn similarity_score(&self, x_seq: &Vec<&str>, y_seq: &Vec<&str>, alignment: &Alignment) -> f64 {
+        score_similarity(alignment, |x, y| {
+            let score = self.compare(x_seq[x], y_seq[y]);
+            if self
+                .correctness
+                .counts(self.matches(x_seq[x], y_seq[y]), score)
+            {
+                Some(score)
+            } else {
+                None
+            }
+        })
     }
 }
 
-/// A Python module implemented in Rust.
-#[pymodule]
-fn sequences(_py: Python, m: &PyModule) -> PyResult<()> {
-    m.add_function(wrap_pyfunction!(align, m)?)?;
-    m.add_class::<AlignmentResult>()?;
-    Ok(())
+/// A [`Scorer`] bound to two sequences, comparing positions by interned token id
+///
+/// Matrix entries between tokens of the two sequences are resolved up front; other pairs
+/// only fall back to string comparison when the matrix has wildcard keys.
+struct InternedScorer<'s, 'a> {
+    scorer: &'s Scorer<'a>,
+    x_seq: &'s [&'a str],
+    y_seq: &'s [&'a str],
+    x_ids: Vec<u32>,
+    y_ids: Vec<u32>,
+    scores: HashMap<(u32, u32), isize>,
+    /// Scores of every pair of ids, for vocabularies of at most [`DENSE_VOCABULARY`] tokens
+    dense: Option<Vec<Vec<isize>>>,
+}
+
+/// Vocabularies up to this size, such as those of DNA or protein sequences, have every pair
+/// of their tokens scored up front, which is faster to look up than sparse scores.
+const DENSE_VOCABULARY: usize = 32;
+
+impl<'s, 'a> InternedScorer<'s, 'a> {
+    fn new(scorer: &'s Scorer<'a>, x_seq: &'s [&'a str], y_seq: &'s [&'a str]) -> Self {
+        let mut vocabulary = intern::Vocabulary::default();
+        let x_ids = vocabulary.intern_all(x_seq);
+        let y_ids = vocabulary.intern_all(y_seq);
+        if vocabulary.len() <= DENSE_VOCABULARY {
+            let tokens = vocabulary.tokens();
+            let dense = tokens
+                .iter()
+                .map(|x| tokens.iter().map(|y| scorer.compare(x, y)).collect())
+                .collect();
+            return InternedScorer {
+                scorer,
+                x_seq,
+                y_seq,
+                x_ids,
+                y_ids,
+                scores: HashMap::new(),
+                dense: Some(dense),
+            };
+        }
+
+        let ambiguous = scorer.ambiguity.iter().flat_map(|ambiguity| {
+            ambiguity.iter().flat_map(|(code, tokens)| {
+                tokens
+                    .iter()
+                    .map(move |token| (code.as_str(), token.as_str()))
+            })
+        });
+        let mut scores = HashMap::new();
+        for (x, y) in scorer.matrix.keys().copied().chain(ambiguous) {
+            if let (Some(x_id), Some(y_id)) = (vocabulary.get(x), vocabulary.get(y)) {
+                scores.insert((x_id, y_id), scorer.compare(x, y));
+                scores.insert((y_id, x_id), scorer.compare(y, x));
+            }
+        }
+
+        InternedScorer {
+            scorer,
+            x_seq,
+            y_seq,
+            x_ids,
+            y_ids,
+            scores,
+            dense: None,
+        }
+    }
+
+    /// Scores aligning `x_seq[x]` with `y_seq[y]`, as [`Scorer::compare`] would.
+    fn compare(&self, x: usize, y: usize) -> isize {
+        let (x_id, y_id) = (self.x_ids[x], self.y_ids[y]);
+        if let Some(ref dense) = self.dense {
+            return dense[x_id as usize][y_id as usize];
+        }
+        if let Some(score) = self.scores.get(&(x_id, y_id)) {
+            return *score;
+        }
+        if !self.scorer.wildcards.is_empty()
+            || self.scorer.idf.is_some()
+            || self.scorer.unicode_normalize
+        {
+            return self.scorer.compare(self.x_seq[x], self.y_seq[y]);
+        }
+        if x_id != y_id && self.scorer.forbid_mismatch {
+            self.scorer.forbidden_score
+        } else if x_id == y_id && !self.scorer.matrix_is_authoritative {
+            self.scorer.match_score
+        } else {
+            self.scorer.mismatch_score
+        }
+    }
+}
+
+/// Weighs the alignment score against the score of its correct columns, given the score of
+/// each aligned pair that counts as correct
+fn score_similarity<F>(alignment: &Alignment, correct_score: F) -> f64
+where
+    F: Fn(usize, usize) -> Option<isize>,
+{
+    score_similarity_weighted(alignment, correct_score, |_| 1f64)
+}
+
+/// Like [`score_similarity`], but weighs each column by `weight` when relating the correct
+/// columns to the whole alignment
+fn score_similarity_weighted<F, W>(alignment: &Alignment, correct_score: F, weight: W) -> f64
+where
+    F: Fn(usize, usize) -> Option<isize>,
+    W: Fn(Step) -> f64,
+{
+    let steps: Vec<Step> = alignment.steps().collect();
+    score_steps_similarity(&steps, alignment.score(), correct_score, weight)
+}
+
+/// Like [`score_similarity_weighted`], for columns `steps` scoring `score` in all
+fn score_steps_similarity<F, W>(steps: &[Step], score: isize, correct_score: F, weight: W) -> f64
+where
+    F: Fn(usize, usize) -> Option<isize>,
+    W: Fn(Step) -> f64,
+{
+    let (dis_correct, num_correct, weight_correct): (i32, u32, f64) =
+        steps
+            .iter()
+            .cloned()
+            .fold((0, 0, 0f64), |(dc, nc, wc), step| match step {
+                Step::Align { x, y } => match correct_score(x, y) {
+                    Some(score) => (dc + score as i32, nc + 1, wc + weight(step)),
+                    None => (dc, nc, wc),
+                },
+                _ => (dc, nc, wc),
+            });
+
+    if num_correct == 0 {
+        return -1f64;
+    }
+
+    let dis = score as i32;
+
+    let sim_align = match dis_correct {
+        0 => 0f64,
+        _ => f64::from(dis) / f64::from(dis_correct),
+    };
+
+    let weight_total: f64 = steps.iter().cloned().map(weight).sum();
+    let sim_significance = if weight_total > 0f64 {
+        weight_correct / weight_total
+    } else {
+        0f64
+    };
+
+    sim_align * sim_significance
+}
+
+/// Finds alignment similarity between two sequences
+///
+/// Pairs scored `FORBIDDEN` in the similarity matrix are never aligned to each other.
+///
+/// Similarity matrix keys ending in `*` match any token starting with what precedes it, so
+/// `("X", "*")` scores `X` against every token it has no other entry for. A pair is looked
+/// up as given, then reversed, then against such keys, longest prefixes first, before
+/// falling back to `match_score` or `mismatch_score`.
+///
+/// With `matrix_is_authoritative`, identical tokens only score `match_score` if the
+/// similarity matrix lists them (in either order); otherwise they score `mismatch_score`.
+///
+/// With `one_based`, the result reports positions in `a` and `b` counting from 1, with ranges
+/// including their ends, as GFF, SAM and VCF do, and takes them that way in `map_position`
+/// and `map_range`. `x_start` and `y_start` are then one more than by default, as are the
+/// positions in `x_to_y`, `matching_runs` and `to_columns`, while `x_end` and `y_end`, now
+/// the positions of the last aligned tokens, are the same. Only the result is affected:
+/// `local_max`, `find_all`, `find_repeats`, `find_inversions` and other helpers always count
+/// from 0, with ranges excluding their ends.
+///
+/// With `collapse_runs`, each run of identical tokens in `a` and in `b` is collapsed to one
+/// token before aligning, so that sequences differing only in the lengths of their runs, as
+/// homopolymer errors make them, align as if identical. The columns are then expanded back
+/// to the original tokens, the tokens one run has beyond the run it is aligned with becoming
+/// gaps. `alignment_score`, `similarity_score`, `score_bounds` and the significance estimate
+/// are on the scale of the collapsed sequences, where each run counts as one token, so a run
+/// of five matches scores one `match_score`. The columns, the counts of matches, mismatches
+/// and gaps, and `score_breakdown` are of the original tokens, scored with the same scorer
+/// and token classes as the collapsed ones; `alignment_score` is then not the sum of
+/// `score_breakdown` unless all runs have length 1.
+///
+/// `correctness_rule` decides which aligned pairs count as correct in the similarity score:
+/// with `"identity"`, the default, pairs of identical (or matching ambiguous) tokens; with
+/// `"positive_score"`, pairs that score above zero, identical or not; and with
+/// `"identity_and_positive"`, identical pairs that also score above zero, so that a matrix
+/// scoring some identical pair negatively does not have it count as correct.
+///
+/// With `unicode_normalize`, tokens that are canonically equivalent in Unicode, such as a
+/// precomposed accented letter and the letter followed by a combining accent, match as if
+/// identical. The tokens themselves appear in the result as given.
+///
+/// With `matrix_as_floor`, a similarity matrix entry only applies where it scores above the
+/// `match_score` or `mismatch_score` the pair would otherwise get, so an incomplete matrix
+/// never makes a pair score worse.
+///
+/// `end_gap_score`, if given, replaces `gap_score` for gaps before the first or after the
+/// last token of either sequence: leading and trailing gaps in `a`, and leading and trailing
+/// gaps in `b`, each forming its own run. `end_gap_open_score`, if given, scores the first
+/// gap of each such run instead, so a terminal run of `n` gaps scores
+/// `end_gap_open_score + (n - 1) * end_gap_score`.
+///
+/// `gap_open_score`, if given, scores the first gap of each run between tokens of the
+/// other sequence instead of `gap_score`, so such a run of `n` gaps scores
+/// `gap_open_score + (n - 1) * gap_score`. Global alignments with gaps scored this way are
+/// filled with Gotoh's algorithm rather than Needleman-Wunsch, as the result's `algorithm`
+/// records.
+///
+/// `secondary_objective` picks among equally scoring alignments: `"min_gap_runs"` or
+/// `"max_gap_runs"` for the fewest or most separate gaps, `"leftmost_gaps"` or
+/// `"rightmost_gaps"` to shift gaps towards the start or the end.
+///
+/// `gap_anchor` also picks among equally scoring alignments, with any options: with
+/// `"left"`, the default, the alignment is the one found aligning the sequences in order,
+/// whose gaps come as late as they can; with `"right"`, the sequences are aligned reversed
+/// and the result reversed back, so that gaps come as early as they can and matching tokens
+/// are anchored to the end, as some diffs prefer. Scores are the same either way, and
+/// `"leftmost_gaps"` and `"rightmost_gaps"` still shift gaps towards the start and the end,
+/// so they decide where gaps go whatever the anchor.
+///
+/// With `local`, only the best scoring pair of subsequences is aligned. `x_drop`, if given,
+/// stops extending local alignments once they score more than `x_drop` below the best seen,
+/// which is faster on long sequences but may miss weaker hits.
+///
+/// `token_class_fn`, if given, maps each token to a class name, and tokens are scored by
+/// class instead. The similarity score still only counts identical tokens as correct, unless
+/// `class_identity` is set, in which case tokens of the same class count.
+///
+/// `max_gaps`, if given, limits a global alignment to that many gapped tokens, raising
+/// `ValueError` if the sequences cannot be aligned within it. Each gapped token counts
+/// against the limit, however gaps are scored, so one long gap uses up several.
+///
+/// `max_gap_length`, if given, forbids a global alignment from gapping more than that many
+/// consecutive tokens of either sequence, raising `ValueError` if that is impossible.
+///
+/// Gaps in `alignments` are marked with `gap_symbol`, unless `delete_symbol` or
+/// `insert_symbol` is given: the former marks the row of `b` where a token of `a` is
+/// deleted, the latter the row of `a` where a token of `b` is inserted.
+///
+/// With `collapse_gaps`, each run of gaps in `alignments` is merged into a single column, of
+/// as many gap symbols against the run's tokens joined by spaces.
+///
+/// `max_columns`, if given, truncates `alignments` to that many columns from its start and
+/// end, setting `truncated`. All other fields describe the full alignment.
+///
+/// With `forbid_mismatch`, only identical tokens are ever aligned, so every other column is a
+/// gap.
+///
+/// With `estimate_significance`, `b` is shuffled `significance_shuffles` times, and the
+/// result's `z_score` and `p_value` place its score among the scores of aligning `a` to the
+/// shuffles. The shuffles are seeded, so the estimate is reproducible.
+///
+/// `idf`, if given, maps tokens to weights, such as their inverse document frequencies, so
+/// that aligning rare tokens counts for more. Each pair of tokens scores what it otherwise
+/// would, times the mean weight of the two, rounded to an integer, so scores may need
+/// scaling up for the weights to matter. Tokens missing from `idf` weigh 1. With
+/// `idf_similarity`, the similarity score also weighs each column by its tokens' weight.
+///
+/// With `char_weighted`, the similarity score weighs each column by the character length of
+/// its widest token rather than counting columns equally.
+///
+/// With `diagnostics`, the result carries an `AlignmentDiagnostics` describing the work done.
+#[pyfunction(
+    match_score = 1,
+    mismatch_score = -1,
+    gap_score = -1,
+    matrix_is_authoritative = false,
+    local = false,
+    diagnostics = false,
+    class_identity = false,
+    char_weighted = false,
+    forbid_mismatch = false,
+    estimate_significance = false,
+    significance_shuffles = 20,
+    collapse_gaps = false,
+    gap_symbol = "String::from(GAP)",
+    matrix_as_floor = false,
+    idf_similarity = false,
+    unicode_normalize = false,
+    correctness_rule = "String::from(\"identity\")",
+    collapse_runs = false,
+    one_based = false,
+    gap_anchor = "String::from(\"left\")"
+)]
+#[allow(clippy::too_many_arguments)]
+fn align(
+    py: Python,
+    a: &PyAny,
+    b: &PyAny,
+    match_score: isize,
+    mismatch_score: isize,
+    gap_score: isize,
+    similarity_matrix: Option<HashMap<(String, String), isize>>,
+    matrix_is_authoritative: bool,
+    end_gap_score: Option<isize>,
+    secondary_objective: Option<String>,
+    local: bool,
+    x_drop: Option<isize>,
+    diagnostics: bool,
+    token_class_fn: Option<PyObject>,
+    class_identity: bool,
+    max_gaps: Option<usize>,
+    max_columns: Option<usize>,
+    char_weighted: bool,
+    forbid_mismatch: bool,
+    max_gap_length: Option<usize>,
+    end_gap_open_score: Option<isize>,
+    estimate_significance: bool,
+    significance_shuffles: usize,
+    collapse_gaps: bool,
+    gap_symbol: String,
+    delete_symbol: Option<String>,
+    insert_symbol: Option<String>,
+    matrix_as_floor: bool,
+    gap_open_score: Option<isize>,
+    idf: Option<HashMap<String, f64>>,
+    idf_similarity: bool,
+    unicode_normalize: bool,
+    correctness_rule: String,
+    collapse_runs: bool,
+    one_based: bool,
+    gap_anchor: String,
+) -> PyResult<AlignmentResult> {
+    let params = AlignmentParams::new(
+        match_score,
+        mismatch_score,
+        gap_score,
+        similarity_matrix,
+        matrix_is_authoritative,
+        end_gap_score,
+        secondary_objective,
+        local,
+        x_drop,
+        diagnostics,
+        token_class_fn,
+        class_identity,
+        max_gaps,
+        max_columns,
+        char_weighted,
+        forbid_mismatch,
+        max_gap_length,
+        end_gap_open_score,
+        estimate_significance,
+        significance_shuffles,
+        collapse_gaps,
+        gap_symbol,
+        delete_symbol,
+        insert_symbol,
+        matrix_as_floor,
+        gap_open_score,
+        idf,
+        idf_similarity,
+        unicode_normalize,
+        correctness_rule,
+        collapse_runs,
+        one_based,
+        gap_anchor,
+    );
+    align_tokens(py, tokens("a", a)?, tokens("b", b)?, &params)
+}
+
+/// Options for `align_with`, named and defaulting like the arguments of `align`
+#[pyclass]
+#[derive(Clone)]
+struct AlignmentParams {
+    #[pyo3(get, set)]
+    match_score: isize,
+    #[pyo3(get, set)]
+    mismatch_score: isize,
+    #[pyo3(get, set)]
+    gap_score: isize,
+    #[pyo3(get, set)]
+    similarity_matrix: Option<HashMap<(String, String), isize>>,
+    #[pyo3(get, set)]
+    matrix_is_authoritative: bool,
+    #[pyo3(get, set)]
+    end_gap_score: Option<isize>,
+    #[pyo3(get, set)]
+    secondary_objective: Option<String>,
+    #[pyo3(get, set)]
+    local: bool,
+    #[pyo3(get, set)]
+    x_drop: Option<isize>,
+    #[pyo3(get, set)]
+    diagnostics: bool,
+    #[pyo3(get, set)]
+    token_class_fn: Option<PyObject>,
+    #[pyo3(get, set)]
+    class_identity: bool,
+    #[pyo3(get, set)]
+    max_gaps: Option<usize>,
+    #[pyo3(get, set)]
+    max_columns: Option<usize>,
+    #[pyo3(get, set)]
+    char_weighted: bool,
+    #[pyo3(get, set)]
+    forbid_mismatch: bool,
+    #[pyo3(get, set)]
+    max_gap_length: Option<usize>,
+    #[pyo3(get, set)]
+    end_gap_open_score: Option<isize>,
+    #[pyo3(get, set)]
+    estimate_significance: bool,
+    #[pyo3(get, set)]
+    significance_shuffles: usize,
+    #[pyo3(get, set)]
+    collapse_gaps: bool,
+    #[pyo3(get, set)]
+    gap_symbol: String,
+    #[pyo3(get, set)]
+    delete_symbol: Option<String>,
+    #[pyo3(get, set)]
+    insert_symbol: Option<String>,
+    #[pyo3(get, set)]
+    matrix_as_floor: bool,
+    #[pyo3(get, set)]
+    gap_open_score: Option<isize>,
+    #[pyo3(get, set)]
+    idf: Option<HashMap<String, f64>>,
+    #[pyo3(get, set)]
+    idf_similarity: bool,
+    #[pyo3(get, set)]
+    unicode_normalize: bool,
+    #[pyo3(get, set)]
+    correctness_rule: String,
+    #[pyo3(get, set)]
+    collapse_runs: bool,
+    #[pyo3(get, set)]
+    one_based: bool,
+    #[pyo3(get, set)]
+    gap_anchor: String,
+}
+
+#[pymethods]
+impl AlignmentParams {
+    #[new]
+    #[args(
+        match_score = 1,
+        mismatch_score = -1,
+        gap_score = -1,
+        matrix_is_authoritative = false,
+        local = false,
+        diagnostics = false,
+        class_identity = false,
+        char_weighted = false,
+        forbid_mismatch = false,
+        estimate_significance = false,
+        significance_shuffles = 20,
+        collapse_gaps = false,
+        gap_symbol = "String::from(GAP)",
+        matrix_as_floor = false,
+        idf_similarity = false,
+        unicode_normalize = false,
+        correctness_rule = "String::from(\"identity\")",
+        collapse_runs = false,
+        one_based = false,
+        gap_anchor = "String::from(\"left\")"
+    )]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        match_score: isize,
+        mismatch_score: isize,
+        gap_score: isize,
+        similarity_matrix: Option<HashMap<(String, String), isize>>,
+        matrix_is_authoritative: bool,
+        end_gap_score: Option<isize>,
+        secondary_objective: Option<String>,
+        local: bool,
+        x_drop: Option<isize>,
+        diagnostics: bool,
+        token_class_fn: Option<PyObject>,
+        class_identity: bool,
+        max_gaps: Option<usize>,
+        max_columns: Option<usize>,
+        char_weighted: bool,
+        forbid_mismatch: bool,
+        max_gap_length: Option<usize>,
+        end_gap_open_score: Option<isize>,
+        estimate_significance: bool,
+        significance_shuffles: usize,
+        collapse_gaps: bool,
+        gap_symbol: String,
+        delete_symbol: Option<String>,
+        insert_symbol: Option<String>,
+        matrix_as_floor: bool,
+        gap_open_score: Option<isize>,
+        idf: Option<HashMap<String, f64>>,
+        idf_similarity: bool,
+        unicode_normalize: bool,
+        correctness_rule: String,
+        collapse_runs: bool,
+        one_based: bool,
+        gap_anchor: String,
+    ) -> Self {
+        AlignmentParams {
+            match_score,
+            mismatch_score,
+            gap_score,
+            similarity_matrix,
+            matrix_is_authoritative,
+            end_gap_score,
+            secondary_objective,
+            local,
+            x_drop,
+            diagnostics,
+            token_class_fn,
+            class_identity,
+            max_gaps,
+            max_columns,
+            char_weighted,
+            forbid_mismatch,
+            max_gap_length,
+            end_gap_open_score,
+            estimate_significance,
+            significance_shuffles,
+            collapse_gaps,
+            gap_symbol,
+            delete_symbol,
+            insert_symbol,
+            matrix_as_floor,
+            gap_open_score,
+            idf,
+            idf_similarity,
+            unicode_normalize,
+            correctness_rule,
+            collapse_runs,
+            one_based,
+            gap_anchor,
+        }
+    }
+}
+
+impl Default for AlignmentParams {
+    fn default() -> Self {
+        AlignmentParams {
+            match_score: 1,
+            mismatch_score: -1,
+            gap_score: -1,
+            similarity_matrix: None,
+            matrix_is_authoritative: false,
+            end_gap_score: None,
+            secondary_objective: None,
+            local: false,
+            x_drop: None,
+            diagnostics: false,
+            token_class_fn: None,
+            class_identity: false,
+            max_gaps: None,
+            max_columns: None,
+            char_weighted: false,
+            forbid_mismatch: false,
+            max_gap_length: None,
+            end_gap_open_score: None,
+            estimate_significance: false,
+            significance_shuffles: 20,
+            collapse_gaps: false,
+            gap_symbol: String::from(GAP),
+            delete_symbol: None,
+            insert_symbol: None,
+            matrix_as_floor: false,
+            gap_open_score: None,
+            idf: None,
+            idf_similarity: false,
+            unicode_normalize: false,
+            correctness_rule: String::from("identity"),
+            collapse_runs: false,
+            one_based: false,
+            gap_anchor: String::from("left"),
+        }
+    }
+}
+
+impl AlignmentParams {
+    fn gaps(&self) -> dp::Gaps {
+        // Local alignments never end in gaps, so only score interior ones.
+        let end_score = match self.end_gap_score {
+            Some(end_gap_score) if !self.local => end_gap_score,
+            _ => self.gap_score,
+        };
+        dp::Gaps {
+            score: self.gap_score,
+            open_score: self.gap_open_score.unwrap_or(self.gap_score),
+            end_score,
+            end_open_score: match self.end_gap_open_score {
+                Some(end_gap_open_score) if !self.local => end_gap_open_score,
+                _ => end_score,
+            },
+        }
+    }
+
+    fn matrix(&self) -> SimilarityMatrix<'_> {
+        self.similarity_matrix
+            .iter()
+            .flatten()
+            .map(|((x, y), score)| ((x.as_str(), y.as_str()), *score))
+            .collect()
+    }
+
+    /// Scores tokens as aligning sequences of `x_len` and `y_len` tokens with these options
+    /// would, looking pairs up in `matrix`, which is [`AlignmentParams::matrix`]
+    fn scorer<'a>(
+        &'a self,
+        matrix: &'a SimilarityMatrix<'a>,
+        x_len: usize,
+        y_len: usize,
+    ) -> Scorer<'a> {
+        Scorer {
+            matrix_is_authoritative: self.matrix_is_authoritative
+                && self.similarity_matrix.is_some(),
+            forbid_mismatch: self.forbid_mismatch,
+            matrix_as_floor: self.matrix_as_floor,
+            idf: self.idf.as_ref(),
+            unicode_normalize: self.unicode_normalize,
+            ..Scorer::new(
+                matrix,
+                self.match_score,
+                self.mismatch_score,
+                self.gaps(),
+                x_len,
+                y_len,
+            )
+        }
+    }
+}
+
+/// Aligns two sequences like `align`, taking its options from `params`
+#[pyfunction]
+fn align_with(
+    py: Python,
+    a: &PyAny,
+    b: &PyAny,
+    params: &AlignmentParams,
+) -> PyResult<AlignmentResult> {
+    align_tokens(py, tokens("a", a)?, tokens("b", b)?, params)
+}
+
+fn align_tokens(
+    py: Python,
+    a: Vec<&str>,
+    b: Vec<&str>,
+    params: &AlignmentParams,
+) -> PyResult<AlignmentResult> {
+    align_tokens_filling(py, a, b, params, run_alignment)
+}
+
+/// Collapses each run of identical tokens in `tokens` to one, returning the length of each
+/// run along with the collapsed tokens
+fn collapse_runs<'a>(tokens: &[&'a str]) -> (Vec<usize>, Vec<&'a str>) {
+    tokens
+        .iter()
+        .dedup_with_count()
+        .map(|(count, token)| (count, *token))
+        .unzip()
+}
+
+/// Repeats each of `tokens` as many times as its run in `runs`, undoing [`collapse_runs`]
+fn repeat_runs<'a>(tokens: &[&'a str], runs: &[usize]) -> Vec<&'a str> {
+    tokens
+        .iter()
+        .zip(runs)
+        .flat_map(|(&token, &run)| std::iter::repeat_n(token, run))
+        .collect()
+}
+
+/// Like [`align_tokens`], but finding the alignment with `fill` in place of
+/// [`run_alignment`]
+fn align_tokens_filling<F>(
+    py: Python,
+    a: Vec<&str>,
+    b: Vec<&str>,
+    params: &AlignmentParams,
+    fill: F,
+) -> PyResult<AlignmentResult>
+where
+    F: FnOnce(
+        &[&str],
+        &[&str],
+        &Scorer,
+        &AlignmentParams,
+        Option<SecondaryObjective>,
+        &mut dp::Stats,
+    ) -> PyResult<Alignment>,
+{
+    let (original, a, b) = if params.collapse_runs {
+        let ((x_runs, a_runs), (y_runs, b_runs)) = (collapse_runs(&a), collapse_runs(&b));
+        (Some((a, b, x_runs, y_runs)), a_runs, b_runs)
+    } else {
+        (None, a, b)
+    };
+    let objective = params
+        .secondary_objective
+        .as_deref()
+        .map(SecondaryObjective::parse)
+        .transpose()?;
+    let anchor_right = match params.gap_anchor.as_str() {
+        "left" => false,
+        "right" => true,
+        _ => {
+            return Err(exceptions::PyValueError::new_err(format!(
+                "gap_anchor must be \"left\" or \"right\", not {:?}",
+                params.gap_anchor
+            )))
+        }
+    };
+    let local = params.local;
+    if local && objective.is_some() {
+        return Err(exceptions::PyValueError::new_err(
+            "secondary_objective is only supported for global alignments",
+        ));
+    }
+    if params.max_gaps.is_some() && (local || objective.is_some()) {
+        return Err(exceptions::PyValueError::new_err(
+            "max_gaps is only supported for global alignments without a secondary objective",
+        ));
+    }
+    if params.max_gap_length.is_some()
+        && (local || objective.is_some() || params.max_gaps.is_some())
+    {
+        return Err(exceptions::PyValueError::new_err(
+            "max_gap_length is only supported for global alignments without a secondary \
+             objective or max_gaps",
+        ));
+    }
+    if !local && params.x_drop.is_some() {
+        return Err(exceptions::PyValueError::new_err(
+            "x_drop is only supported for local alignments",
+        ));
+    }
+    if matches!(params.x_drop, Some(x_drop) if x_drop < 0) {
+        return Err(exceptions::PyValueError::new_err(
+            "x_drop must not be negative",
+        ));
+    }
+    if local && params.gap_open_score.is_some() {
+        return Err(exceptions::PyValueError::new_err(
+            "gap_open_score is only supported for global alignments",
+        ));
+    }
+    if let Some((token, _)) = params
+        .idf
+        .iter()
+        .flatten()
+        .find(|(_, weight)| !weight.is_finite() || **weight < 0f64)
+    {
+        return Err(exceptions::PyValueError::new_err(format!(
+            "idf weight of {:?} must be finite and not negative",
+            token
+        )));
+    }
+    let gaps = params.gaps();
+    if gaps.is_affine() && (params.max_gaps.is_some() || params.max_gap_length.is_some()) {
+        return Err(exceptions::PyValueError::new_err(
+            "max_gaps and max_gap_length do not support gap open scores",
+        ));
+    }
+    let matrix = params.matrix();
+    let scorer = Scorer {
+        correctness: Correctness::parse(&params.correctness_rule)?,
+        ..params.scorer(&matrix, a.len(), b.len())
+    };
+
+    let classes = match params.token_class_fn {
+        Some(ref token_class_fn) => Some(token_classes(py, token_class_fn, &a, &b)?),
+        None => None,
+    };
+    let (x_classes, y_classes): (Vec<&str>, Vec<&str>) = match classes {
+        Some((ref x_classes, ref y_classes)) => (
+            x_classes.iter().map(String::as_str).collect(),
+            y_classes.iter().map(String::as_str).collect(),
+        ),
+        None => (a.clone(), b.clone()),
+    };
+
+    let mut stats = dp::Stats::default();
+    let alignment = if anchor_right {
+        let (mut x_reversed, mut y_reversed) = (x_classes.clone(), y_classes.clone());
+        x_reversed.reverse();
+        y_reversed.reverse();
+        let alignment = fill(
+            &x_reversed,
+            &y_reversed,
+            &scorer,
+            params,
+            objective.map(SecondaryObjective::mirrored),
+            &mut stats,
+        )?;
+        reverse_alignment(&alignment, a.len(), b.len())
+    } else {
+        fill(
+            &x_classes, &y_classes, &scorer, params, objective, &mut stats,
+        )?
+    };
+    let mut result = AlignmentResult::from_alignment(&a, &b, &scorer, &alignment);
+    result.cells_evaluated = stats.cells;
+    result.algorithm = Some(algorithm(params, objective, gaps));
+    if local {
+        // A local alignment may be empty, or align a long stretch of the best pairs without
+        // the gaps a global one would need.
+        let (_, best_pair) = scorer.pair_bounds();
+        result.score_bounds = (0, best_pair.max(0) * a.len().min(b.len()) as isize);
+    }
+    if classes.is_some() {
+        result.column_scores = column_scores(&x_classes, &y_classes, &scorer, &alignment);
+    }
+    if classes.is_some() || params.char_weighted || params.idf_similarity {
+        let (x_identity, y_identity) = if params.class_identity {
+            (&x_classes, &y_classes)
+        } else {
+            (&a, &b)
+        };
+        let correct_score = |x: usize, y: usize| {
+            let score = scorer.compare(x_classes[x], y_classes[y]);
+            if scorer
+                .correctness
+                .counts(scorer.matches(x_identity[x], y_identity[y]), score)
+            {
+                Some(score)
+            } else {
+                None
+            }
+        };
+        let weight = |step: Step| {
+            let width = if params.char_weighted {
+                f64::from(column_width(&a, &b, step.clone()))
+            } else {
+                1f64
+            };
+            if params.idf_similarity {
+                width * scorer.idf_weight(&x_classes, &y_classes, step)
+            } else {
+                width
+            }
+        };
+        result.similarity_score = score_similarity_weighted(&alignment, correct_score, weight);
+    }
+    if params.diagnostics {
+        result.diagnostics = Some(AlignmentDiagnostics::from(stats));
+    }
+    if params.estimate_significance {
+        if params.significance_shuffles == 0 {
+            return Err(exceptions::PyValueError::new_err(
+                "significance_shuffles must be positive",
+            ));
+        }
+        let mut rng = random::SplitMix64::new(0);
+        let mut shuffled = y_classes.clone();
+        let mut null_scores = vec![];
+        for _ in 0..params.significance_shuffles {
+            rng.shuffle(&mut shuffled);
+            let null = run_alignment(
+                &x_classes,
+                &shuffled,
+                &scorer,
+                params,
+                objective,
+                &mut dp::Stats::default(),
+            );
+            // Shuffles that cannot be aligned under the constraints do not count.
+            if let Ok(null) = null {
+                null_scores.push(null.score());
+            }
+        }
+        (result.z_score, result.p_value) = significance(alignment.score(), &null_scores);
+    }
+    if let Some((a, b, x_runs, y_runs)) = original {
+        let classes = (
+            repeat_runs(&x_classes, &x_runs),
+            repeat_runs(&y_classes, &y_runs),
+        );
+        result = result.expand_runs(&a, &b, &classes, &x_runs, &y_runs, &scorer);
+    }
+    result.set_gap_symbols(GapSymbols {
+        delete: params
+            .delete_symbol
+            .clone()
+            .unwrap_or_else(|| params.gap_symbol.clone()),
+        insert: params
+            .insert_symbol
+            .clone()
+            .unwrap_or_else(|| params.gap_symbol.clone()),
+    });
+    if params.collapse_gaps {
+        result.collapse_gaps();
+    }
+    if let Some(max_columns) = params.max_columns {
+        result.truncate(max_columns);
+    }
+    result.one_based = params.one_based;
+    Ok(result)
+}
+
+/// Fills and traces the alignment `params` asks for
+fn run_alignment(
+    x_seq: &[&str],
+    y_seq: &[&str],
+    scorer: &Scorer,
+    params: &AlignmentParams,
+    objective: Option<SecondaryObjective>,
+    stats: &mut dp::Stats,
+) -> PyResult<Alignment> {
+    if params.local {
+        Ok(local_alignment(x_seq, y_seq, scorer, params.x_drop, stats))
+    } else if let Some(max_gaps) = params.max_gaps {
+        let interned = InternedScorer::new(scorer, x_seq, y_seq);
+        dp::global_max_gaps(
+            x_seq.len(),
+            y_seq.len(),
+            scorer.gaps,
+            max_gaps,
+            stats,
+            |x, y| interned.compare(x, y),
+        )
+        .ok_or_else(|| {
+            exceptions::PyValueError::new_err(format!(
+                "sequences cannot be aligned with at most {} gaps",
+                max_gaps
+            ))
+        })
+    } else if let Some(max_gap_length) = params.max_gap_length {
+        let interned = InternedScorer::new(scorer, x_seq, y_seq);
+        dp::global_max_gap_length(
+            x_seq.len(),
+            y_seq.len(),
+            scorer.gaps,
+            max_gap_length,
+            stats,
+            |x, y| interned.compare(x, y),
+        )
+        .ok_or_else(|| {
+            exceptions::PyValueError::new_err(format!(
+                "sequences cannot be aligned with gaps of at most {} tokens",
+                max_gap_length
+            ))
+        })
+    } else {
+        global_alignment(x_seq, y_seq, scorer, objective, stats)
+    }
+}
+
+/// Names the algorithm [`run_alignment`] fills with: Gotoh's whenever gap runs are scored
+/// or counted as a whole, and otherwise Needleman-Wunsch or, for local alignments,
+/// Smith-Waterman
+fn algorithm(
+    params: &AlignmentParams,
+    objective: Option<SecondaryObjective>,
+    gaps: dp::Gaps,
+) -> &'static str {
+    let counts_runs = matches!(
+        objective,
+        Some(SecondaryObjective::MinGapRuns | SecondaryObjective::MaxGapRuns)
+    );
+    if params.local {
+        "smith_waterman"
+    } else if gaps.is_affine() || counts_runs {
+        "gotoh"
+    } else {
+        "needleman_wunsch"
+    }
+}
+
+/// Places `score` within `null_scores`, as a z-score (if they vary) and an empirical p-value
+/// of scoring at least as well
+fn significance(score: isize, null_scores: &[isize]) -> (Option<f64>, Option<f64>) {
+    if null_scores.is_empty() {
+        return (None, None);
+    }
+    let n = null_scores.len() as f64;
+    let (mean, deviation) = mean_and_deviation(null_scores);
+    let z_score = if deviation > 0f64 {
+        Some((score as f64 - mean) / deviation)
+    } else {
+        None
+    };
+    let at_least = null_scores.iter().filter(|null| **null >= score).count() as f64;
+    (z_score, Some((at_least + 1f64) / (n + 1f64)))
+}
+
+/// Mean and population standard deviation of some non-empty `scores`
+fn mean_and_deviation(scores: &[isize]) -> (f64, f64) {
+    let n = scores.len() as f64;
+    let mean = scores.iter().sum::<isize>() as f64 / n;
+    let variance = scores
+        .iter()
+        .map(|score| (*score as f64 - mean).powi(2))
+        .sum::<f64>()
+        / n;
+    (mean, variance.sqrt())
+}
+
+/// Aligns sequences with a set of options, whose similarity matrix can be edited in place
+/// between alignments
+///
+/// `params` is the `AlignmentParams` object itself, not a copy, so assigning to its fields,
+/// here or through the object it was created with, changes later alignments.
+#[pyclass]
+struct Aligner {
+    #[pyo3(get, set)]
+    params: Py<AlignmentParams>,
+    prefix_fill: Option<PrefixFill>,
+}
+
+/// The fill of one sequence against a prefix of another, and what it depends on
+struct PrefixFill {
+    x_seq: Vec<String>,
+    prefix: Vec<String>,
+    scoring: PrefixScoring,
+    fill: dp::GrowingGlobal,
+}
+
+/// Options that change how a [`PrefixFill`] scores
+#[derive(PartialEq)]
+struct PrefixScoring {
+    match_score: isize,
+    mismatch_score: isize,
+    gap_score: isize,
+    similarity_matrix: Option<HashMap<(String, String), isize>>,
+    matrix_is_authoritative: bool,
+    matrix_as_floor: bool,
+    idf: Option<HashMap<String, f64>>,
+    unicode_normalize: bool,
+}
+
+impl PrefixScoring {
+    fn of(params: &AlignmentParams) -> Self {
+        PrefixScoring {
+            match_score: params.match_score,
+            mismatch_score: params.mismatch_score,
+            gap_score: params.gap_score,
+            similarity_matrix: params.similarity_matrix.clone(),
+            matrix_is_authoritative: params.matrix_is_authoritative,
+            matrix_as_floor: params.matrix_as_floor,
+            idf: params.idf.clone(),
+            unicode_normalize: params.unicode_normalize,
+        }
+    }
+}
+
+#[pymethods]
+impl Aligner {
+    #[new]
+    fn new(py: Python, params: Option<Py<AlignmentParams>>) -> PyResult<Self> {
+        Ok(Aligner {
+            params: match params {
+                Some(params) => params,
+                None => Py::new(py, AlignmentParams::default())?,
+            },
+            prefix_fill: None,
+        })
+    }
+
+    fn align(&self, py: Python, a: &PyAny, b: &PyAny) -> PyResult<AlignmentResult> {
+        align_with(py, a, b, &self.params.borrow(py))
+    }
+
+    /// Aligns `a` to `b` like `align`, reusing the fill of `a` against the first
+    /// `prefix_len` tokens of `b` from the previous call if it aligned the same `a` to the
+    /// same prefix with the same scores, and filling only the rest of `b`
+    ///
+    /// Results always equal those of `align`. The fill is only shared for global alignments
+    /// with the same score for every gap, and no secondary objective, token classes, gap
+    /// limits, forbidden pairs or `forbid_mismatch`; otherwise `b` is aligned in full.
+    fn align_with_shared_prefix(
+        &mut self,
+        py: Python,
+        prefix_len: usize,
+        a: &PyAny,
+        b: &PyAny,
+    ) -> PyResult<AlignmentResult> {
+        let (a, b) = (tokens("a", a)?, tokens("b", b)?);
+        if prefix_len > b.len() {
+            return Err(exceptions::PyValueError::new_err(format!(
+                "prefix_len {} exceeds the length {} of b",
+                prefix_len,
+                b.len()
+            )));
+        }
+        let prefix_fill = &mut self.prefix_fill;
+        align_tokens_filling(
+            py,
+            a,
+            b,
+            &self.params.borrow(py),
+            |x_seq, y_seq, scorer, params, objective, stats| {
+                let shareable = !params.local
+                    && !params.collapse_runs
+                    && params.gap_anchor == "left"
+                    && objective.is_none()
+                    && params.token_class_fn.is_none()
+                    && params.max_gaps.is_none()
+                    && params.max_gap_length.is_none()
+                    && !params.forbid_mismatch
+                    && scorer.gaps.is_uniform()
+                    && scorer.matrix.values().all(|score| *score != FORBIDDEN);
+                if !shareable {
+                    return run_alignment(x_seq, y_seq, scorer, params, objective, stats);
+                }
+
+                let (prefix, rest) = y_seq.split_at(prefix_len);
+                let scoring = PrefixScoring::of(params);
+                let cached = matches!(
+                    prefix_fill,
+                    Some(cached) if cached.x_seq == x_seq && cached.prefix == prefix
+                        && cached.scoring == scoring
+                );
+                if !cached {
+                    let mut fill = dp::GrowingGlobal::new(x_seq.len(), scorer.gaps.score);
+                    for y_token in prefix {
+                        fill.push(stats, |x| scorer.compare(x_seq[x], y_token));
+                    }
+                    *prefix_fill = Some(PrefixFill {
+                        x_seq: x_seq.iter().map(|token| token.to_string()).collect(),
+                        prefix: prefix.iter().map(|token| token.to_string()).collect(),
+                        scoring,
+                        fill,
+                    });
+                }
+
+                let mut fill = prefix_fill
+                    .as_ref()
+                    .expect("the prefix was just filled")
+                    .fill
+                    .clone();
+                for y_token in rest {
+                    fill.push(stats, |x| scorer.compare(x_seq[x], y_token));
+                }
+                Ok(fill.alignment(stats))
+            },
+        )
+    }
+
+    /// Sets the similarity matrix score of `(x, y)`
+    fn set_score(&self, py: Python, x: &str, y: &str, score: isize) {
+        self.params
+            .borrow_mut(py)
+            .similarity_matrix
+            .get_or_insert_with(HashMap::new)
+            .insert((x.to_string(), y.to_string()), score);
+    }
+
+    /// Returns the similarity matrix score of `(x, y)`, or else of `(y, x)`, if either is set
+    fn get_score(&self, py: Python, x: &str, y: &str) -> Option<isize> {
+        let params = self.params.borrow(py);
+        let matrix = params.similarity_matrix.as_ref()?;
+        let (x, y) = (x.to_string(), y.to_string());
+        matrix
+            .get(&(x.clone(), y.clone()))
+            .or_else(|| matrix.get(&(y, x)))
+            .copied()
+    }
+
+    /// Returns the similarity matrix as it is looked up, with `(y, x)` filled in for every
+    /// `(x, y)` whose reverse is not set
+    fn matrix(&self, py: Python) -> HashMap<(String, String), isize> {
+        let params = self.params.borrow(py);
+        let mut matrix = params.similarity_matrix.clone().unwrap_or_default();
+        for ((x, y), score) in params.similarity_matrix.iter().flatten() {
+            matrix.entry((y.clone(), x.clone())).or_insert(*score);
+        }
+        matrix
+    }
+}
+
+/// Returns the substitution matrix called `name`, for use as a `similarity_matrix`
+///
+/// Only `"BLOSUM62"` is known, in any case. Unknown names raise `ValueError`.
+#[pyfunction]
+fn named_matrix(name: &str) -> PyResult<HashMap<(String, String), isize>> {
+    matrices::named(name).ok_or_else(|| {
+        exceptions::PyValueError::new_err(format!("unknown similarity matrix {:?}", name))
+    })
+}
+
+/// Fields a scoring config read by `load_config` may have
+const CONFIG_FIELDS: [&str; 7] = [
+    "match_score",
+    "mismatch_score",
+    "gap_score",
+    "gap_open_score",
+    "end_gap_score",
+    "similarity_matrix",
+    "mode",
+];
+
+/// Builds an `Aligner` from the scoring config in the JSON file at `path`
+///
+/// The config is an object with integer `match_score`, `mismatch_score` and `gap_score`
+/// fields, and optionally:
+///
+/// - `gap_open_score` and `end_gap_score`, integers as for `align`;
+/// - `similarity_matrix`, a list of `[x, y, score]` triples, as JSON objects only have string
+///   keys;
+/// - `mode`, either `"global"`, the default, or `"local"`.
+///
+/// Any other field, or a field of the wrong type, is an error.
+#[pyfunction]
+fn load_config(py: Python, path: &str) -> PyResult<Aligner> {
+    let text = std::fs::read_to_string(path).map_err(|error| {
+        exceptions::PyOSError::new_err(format!("cannot read config {}: {}", path, error))
+    })?;
+    let config = py
+        .import("json")?
+        .call_method1("loads", (text,))
+        .map_err(|error| {
+            exceptions::PyValueError::new_err(format!(
+                "config {} is not valid JSON: {}",
+                path, error
+            ))
+        })?;
+    let config: &PyDict = config
+        .downcast()
+        .map_err(|_| exceptions::PyValueError::new_err("config must be a JSON object"))?;
+
+    for key in config.keys() {
+        let key: &str = key.extract()?;
+        if !CONFIG_FIELDS.contains(&key) {
+            return Err(exceptions::PyValueError::new_err(format!(
+                "unknown config field {:?}",
+                key
+            )));
+        }
+    }
+    let field = |key: &str, expected: &str| -> PyResult<Option<&PyAny>> {
+        match config.get_item(key) {
+            Some(value) if value.is_none() => Err(exceptions::PyValueError::new_err(format!(
+                "config field {:?} must be {}",
+                key, expected
+            ))),
+            value => Ok(value),
+        }
+    };
+    let integer = |key: &str| -> PyResult<Option<isize>> {
+        let invalid = || {
+            exceptions::PyValueError::new_err(format!("config field {:?} must be an integer", key))
+        };
+        match field(key, "an integer")? {
+            // JSON booleans are Python ints too.
+            Some(value) if value.is_instance_of::<PyBool>()? => Err(invalid()),
+            Some(value) => value.extract().map(Some).map_err(|_| invalid()),
+            None => Ok(None),
+        }
+    };
+    let required = |key: &str| -> PyResult<isize> {
+        integer(key)?.ok_or_else(|| {
+            exceptions::PyValueError::new_err(format!("config is missing {:?}", key))
+        })
+    };
+
+    let triple = |triple: &PyAny| -> Option<((String, String), isize)> {
+        // JSON arrays load as lists, which do not extract as tuples.
+        match triple.extract::<Vec<&PyAny>>().ok()?[..] {
+            [x, y, score] if !score.is_instance_of::<PyBool>().ok()? => Some((
+                (x.extract().ok()?, y.extract().ok()?),
+                score.extract().ok()?,
+            )),
+            _ => None,
+        }
+    };
+    let similarity_matrix = match field("similarity_matrix", "a list of [x, y, score] triples")? {
+        Some(triples) => Some(
+            triples
+                .extract::<Vec<&PyAny>>()
+                .ok()
+                .and_then(|triples| triples.into_iter().map(triple).collect())
+                .ok_or_else(|| {
+                    exceptions::PyValueError::new_err(
+                        "config field \"similarity_matrix\" must be a list of [x, y, score] \
+                         triples",
+                    )
+                })?,
+        ),
+        None => None,
+    };
+    let local = match field("mode", "\"global\" or \"local\"")? {
+        None => false,
+        Some(mode) => match mode.extract::<&str>() {
+            Ok("global") => false,
+            Ok("local") => true,
+            _ => {
+                return Err(exceptions::PyValueError::new_err(format!(
+                    "config field \"mode\" must be \"global\" or \"local\", not {}",
+                    mode.repr()?
+                )))
+            }
+        },
+    };
+
+    let params = Py::new(
+        py,
+        AlignmentParams {
+            match_score: required("match_score")?,
+            mismatch_score: required("mismatch_score")?,
+            gap_score: required("gap_score")?,
+            gap_open_score: integer("gap_open_score")?,
+            end_gap_score: integer("end_gap_score")?,
+            similarity_matrix,
+            local,
+            ..AlignmentParams::default()
+        },
+    )?;
+    Aligner::new(py, Some(params))
+}
+
+/// Aligns `(a, b)` pairs from a Python iterable one at a time, as they are requested
+#[pyfunction]
+fn align_iter(
+    py: Python,
+    pairs: &PyAny,
+    params: Option<AlignmentParams>,
+) -> PyResult<AlignmentIter> {
+    Ok(AlignmentIter {
+        pairs: PyIterator::from_object(py, pairs)?.into(),
+        params: params.unwrap_or_default(),
+        index: 0,
+    })
+}
+
+#[pyclass]
+struct AlignmentIter {
+    pairs: Py<PyIterator>,
+    params: AlignmentParams,
+    /// Index of the next pair, for error messages
+    index: usize,
+}
+
+#[pymethods]
+impl AlignmentIter {
+    fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python) -> PyResult<Option<AlignmentResult>> {
+        let mut pairs = self.pairs.as_ref(py);
+        let pair = match pairs.next() {
+            Some(pair) => pair?,
+            None => return Ok(None),
+        };
+        let name = format!("pairs[{}]", self.index);
+        self.index += 1;
+        let (a, b): (&PyAny, &PyAny) = pair.extract().map_err(|_| {
+            exceptions::PyTypeError::new_err(format!(
+                "{} must be an (a, b) pair of token lists, got {}",
+                name,
+                type_name(pair)
+            ))
+        })?;
+        let a = tokens(&format!("{}[0]", name), a)?;
+        let b = tokens(&format!("{}[1]", name), b)?;
+        align_tokens(py, a, b, &self.params).map(Some)
+    }
+}
+
+/// Globally aligns a fixed sequence `a` to a sequence `b` that grows one token at a time
+///
+/// Each pushed token only fills its own row of the alignment, in time linear in the length of
+/// `a`, and the alignment is traced only when asked for. Results equal those of `align` with
+/// the same scores.
+#[pyclass]
+struct IncrementalAligner {
+    #[pyo3(get)]
+    a: Vec<String>,
+    #[pyo3(get)]
+    b: Vec<String>,
+    match_score: isize,
+    mismatch_score: isize,
+    gap_score: isize,
+    fill: dp::GrowingGlobal,
+    stats: dp::Stats,
+}
+
+impl IncrementalAligner {
+    fn scorer<'a>(&self, matrix: &'a SimilarityMatrix<'a>) -> Scorer<'a> {
+        Scorer::new(
+            matrix,
+            self.match_score,
+            self.mismatch_score,
+            dp::Gaps::linear(self.gap_score),
+            self.a.len(),
+            self.b.len(),
+        )
+    }
+}
+
+#[pymethods]
+impl IncrementalAligner {
+    #[new]
+    #[args(match_score = 1, mismatch_score = -1, gap_score = -1)]
+    fn new(a: Vec<String>, match_score: isize, mismatch_score: isize, gap_score: isize) -> Self {
+        IncrementalAligner {
+            fill: dp::GrowingGlobal::new(a.len(), gap_score),
+            a,
+            b: vec![],
+            match_score,
+            mismatch_score,
+            gap_score,
+            stats: dp::Stats::default(),
+        }
+    }
+
+    /// Appends `token` to `b`, returning the score of the best alignment so far
+    fn push_b(&mut self, token: String) -> isize {
+        let matrix = SimilarityMatrix::new();
+        let scorer = self.scorer(&matrix);
+        self.fill
+            .push(&mut self.stats, |x| scorer.compare(&self.a[x], &token));
+        self.b.push(token);
+        self.fill.score()
+    }
+
+    /// Returns the best alignment of `a` to the tokens pushed so far
+    fn alignment(&self) -> AlignmentResult {
+        let matrix = SimilarityMatrix::new();
+        let scorer = self.scorer(&matrix);
+        let a: Vec<&str> = self.a.iter().map(String::as_str).collect();
+        let b: Vec<&str> = self.b.iter().map(String::as_str).collect();
+        let alignment = self.fill.alignment(&mut dp::Stats::default());
+        AlignmentResult {
+            cells_evaluated: self.stats.cells,
+            algorithm: Some("needleman_wunsch"),
+            ..AlignmentResult::from_alignment(&a, &b, &scorer, &alignment)
+        }
+    }
+}
+
+/// Maps the tokens of both sequences to their classes, calling `token_class_fn` once per
+/// distinct token
+fn token_classes<'a>(
+    py: Python,
+    token_class_fn: &PyObject,
+    a: &[&'a str],
+    b: &[&'a str],
+) -> PyResult<(Vec<String>, Vec<String>)> {
+    let mut cache: HashMap<&'a str, String> = HashMap::new();
+    let mut classify = |seq: &[&'a str]| -> PyResult<Vec<String>> {
+        seq.iter()
+            .map(|token| match cache.get(token) {
+                Some(class) => Ok(class.clone()),
+                None => {
+                    let class: String = token_class_fn.call1(py, (*token,))?.extract(py)?;
+                    cache.insert(token, class.clone());
+                    Ok(class)
+                }
+            })
+            .collect()
+    };
+    Ok((classify(a)?, classify(b)?))
+}
+
+/// Like `align`, but only computes the similarity score, without tracing aligned columns
+///
+/// Only the scoring arguments below are taken. The score then equals the `similarity_score`
+/// of `align` called with the same arguments and its other options left at their defaults,
+/// so rankings can use this and only align the pairs they keep.
+#[pyfunction(
+    match_score = 1,
+    mismatch_score = -1,
+    gap_score = -1,
+    matrix_is_authoritative = false
+)]
+#[allow(clippy::too_many_arguments)]
+fn similarity(
+    a: &PyAny,
+    b: &PyAny,
+    match_score: isize,
+    mismatch_score: isize,
+    gap_score: isize,
+    similarity_matrix: Option<SimilarityMatrix>,
+    matrix_is_authoritative: bool,
+    end_gap_score: Option<isize>,
+    secondary_objective: Option<&str>,
+) -> PyResult<f64> {
+    similarity_of_tokens(
+        tokens("a", a)?,
+        tokens("b", b)?,
+        match_score,
+        mismatch_score,
+        gap_score,
+        similarity_matrix,
+        matrix_is_authoritative,
+        end_gap_score,
+        secondary_objective,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn similarity_of_tokens(
+    a: Vec<&str>,
+    b: Vec<&str>,
+    match_score: isize,
+    mismatch_score: isize,
+    gap_score: isize,
+    similarity_matrix: Option<SimilarityMatrix>,
+    matrix_is_authoritative: bool,
+    end_gap_score: Option<isize>,
+    secondary_objective: Option<&str>,
+) -> PyResult<f64> {
+    let objective = secondary_objective
+        .map(SecondaryObjective::parse)
+        .transpose()?;
+    let gaps = dp::Gaps {
+        end_score: end_gap_score.unwrap_or(gap_score),
+        end_open_score: end_gap_score.unwrap_or(gap_score),
+        ..dp::Gaps::linear(gap_score)
+    };
+    let matrix_is_authoritative = matrix_is_authoritative && similarity_matrix.is_some();
+    let matrix = similarity_matrix.unwrap_or_default();
+    let scorer = Scorer {
+        matrix_is_authoritative,
+        ..Scorer::new(&matrix, match_score, mismatch_score, gaps, a.len(), b.len())
+    };
+
+    let global_alignment = global_alignment(&a, &b, &scorer, objective, &mut dp::Stats::default())?;
+    Ok(scorer.similarity_score(&a, &b, &global_alignment))
+}
+
+/// A corpus index ranked by its similarity, so that higher similarities and, among equals,
+/// lower indices are greater
+struct Neighbor {
+    similarity: f64,
+    index: usize,
+}
+
+impl Ord for Neighbor {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.similarity
+            .total_cmp(&other.similarity)
+            .then(other.index.cmp(&self.index))
+    }
+}
+
+impl PartialOrd for Neighbor {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Neighbor {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Neighbor {}
+
+/// Returns the `(index, similarity)` of the `k` sequences of `corpus` most similar to
+/// `query`, most similar first, scoring each like `similarity`
+///
+/// Only the best `k` scores seen are kept at any time. Equally similar sequences are ranked
+/// by their index.
+#[pyfunction(
+    match_score = 1,
+    mismatch_score = -1,
+    gap_score = -1,
+    matrix_is_authoritative = false
+)]
+#[allow(clippy::too_many_arguments)]
+fn nearest(
+    query: &PyAny,
+    corpus: &PyAny,
+    k: usize,
+    match_score: isize,
+    mismatch_score: isize,
+    gap_score: isize,
+    similarity_matrix: Option<SimilarityMatrix>,
+    matrix_is_authoritative: bool,
+    end_gap_score: Option<isize>,
+    secondary_objective: Option<&str>,
+) -> PyResult<Vec<(usize, f64)>> {
+    let query = tokens("query", query)?;
+    let corpus = token_lists("corpus", corpus)?;
+    if k == 0 {
+        return Ok(vec![]);
+    }
+    let mut best: BinaryHeap<Reverse<Neighbor>> = BinaryHeap::with_capacity(k + 1);
+    for (index, sequence) in corpus.into_iter().enumerate() {
+        let similarity = similarity_of_tokens(
+            query.clone(),
+            sequence,
+            match_score,
+            mismatch_score,
+            gap_score,
+            similarity_matrix.clone(),
+            matrix_is_authoritative,
+            end_gap_score,
+            secondary_objective,
+        )?;
+        best.push(Reverse(Neighbor { similarity, index }));
+        if best.len() > k {
+            best.pop();
+        }
+    }
+    Ok(best
+        .into_sorted_vec()
+        .into_iter()
+        .map(|Reverse(neighbor)| (neighbor.index, neighbor.similarity))
+        .collect())
+}
+
+/// An alignment of a query to the reference at `index`, ranked by score and then, among
+/// equal scores, earliest reference first
+struct RankedMatch {
+    index: usize,
+    result: AlignmentResult,
+}
+
+impl RankedMatch {
+    fn key(&self) -> (isize, Reverse<usize>) {
+        (self.result.alignment_score, Reverse(self.index))
+    }
+}
+
+impl Ord for RankedMatch {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl PartialOrd for RankedMatch {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for RankedMatch {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for RankedMatch {}
+
+/// Returns `(index, result)` for the `k` sequences of `refs` that `query` aligns to with
+/// the highest scores, best first, aligning each with `params` like `align_with`
+///
+/// Only the best `k` alignments seen are kept at any time, and all of `refs` are returned if
+/// there are fewer than `k`. Equally scoring references are ranked by their index.
+#[pyfunction]
+fn top_k_matches(
+    py: Python,
+    query: &PyAny,
+    refs: &PyAny,
+    k: usize,
+    params: Option<AlignmentParams>,
+) -> PyResult<Vec<(usize, AlignmentResult)>> {
+    let query = tokens("query", query)?;
+    let refs = token_lists("refs", refs)?;
+    let params = params.unwrap_or_default();
+    if k == 0 {
+        return Ok(vec![]);
+    }
+    let mut best: BinaryHeap<Reverse<RankedMatch>> = BinaryHeap::with_capacity(k + 1);
+    for (index, reference) in refs.into_iter().enumerate() {
+        let result = align_tokens(py, query.clone(), reference, &params)?;
+        best.push(Reverse(RankedMatch { index, result }));
+        if best.len() > k {
+            best.pop();
+        }
+    }
+    Ok(best
+        .into_sorted_vec()
+        .into_iter()
+        .map(|Reverse(ranked)| (ranked.index, ranked.result))
+        .collect())
+}
+
+/// Returns whether aligning `a` and `b` like `align` scores at least `threshold` by `metric`
+///
+/// `metric` is `"similarity"` for the similarity score, `"percent_identity"` for the
+/// percentage of identical columns, or `"normalized"` for the alignment score divided by that
+/// of aligning the longer sequence to itself, which needs a positive `match_score`.
+#[pyfunction(
+    metric = "\"similarity\"",
+    match_score = 1,
+    mismatch_score = -1,
+    gap_score = -1
+)]
+#[allow(clippy::too_many_arguments)]
+fn is_match(
+    py: Python,
+    a: &PyAny,
+    b: &PyAny,
+    threshold: f64,
+    metric: &str,
+    match_score: isize,
+    mismatch_score: isize,
+    gap_score: isize,
+    similarity_matrix: Option<HashMap<(String, String), isize>>,
+) -> PyResult<bool> {
+    let (a, b) = (tokens("a", a)?, tokens("b", b)?);
+    let longest = a.len().max(b.len());
+    let params = AlignmentParams {
+        match_score,
+        mismatch_score,
+        gap_score,
+        similarity_matrix,
+        ..AlignmentParams::default()
+    };
+    let result = align_tokens(py, a, b, &params)?;
+    let value = match metric {
+        "similarity" => result.similarity_score,
+        "percent_identity" => result.percent_identity(),
+        "normalized" if match_score > 0 => {
+            if longest == 0 {
+                1f64
+            } else {
+                result.alignment_score as f64 / (match_score as f64 * longest as f64)
+            }
+        }
+        "normalized" => {
+            return Err(exceptions::PyValueError::new_err(
+                "the normalized metric needs a positive match_score",
+            ))
+        }
+        _ => {
+            return Err(exceptions::PyValueError::new_err(format!(
+                "unknown metric {:?}",
+                metric
+            )))
+        }
+    };
+    Ok(value >= threshold)
+}
+
+/// Criterion for choosing among co-optimal alignments
+#[derive(Clone, Copy)]
+enum SecondaryObjective {
+    MinGapRuns,
+    MaxGapRuns,
+    LeftmostGaps,
+    RightmostGaps,
+}
+
+impl SecondaryObjective {
+    fn parse(name: &str) -> PyResult<SecondaryObjective> {
+        match name {
+            "min_gap_runs" => Ok(SecondaryObjective::MinGapRuns),
+            "max_gap_runs" => Ok(SecondaryObjective::MaxGapRuns),
+            "leftmost_gaps" => Ok(SecondaryObjective::LeftmostGaps),
+            "rightmost_gaps" => Ok(SecondaryObjective::RightmostGaps),
+            _ => Err(exceptions::PyValueError::new_err(format!(
+                "unknown secondary objective {:?}",
+                name
+            ))),
+        }
+    }
+
+    /// The objective picking the reverse of what this one picks in reversed sequences
+    fn mirrored(self) -> SecondaryObjective {
+        match self {
+            SecondaryObjective::LeftmostGaps => SecondaryObjective::RightmostGaps,
+            SecondaryObjective::RightmostGaps => SecondaryObjective::LeftmostGaps,
+            objective => objective,
+        }
+    }
+}
+
+/// What counts as a correct column in similarity scores
+#[derive(Clone, Copy)]
+enum Correctness {
+    Identity,
+    PositiveScore,
+    IdentityAndPositive,
+}
+
+impl Correctness {
+    fn parse(name: &str) -> PyResult<Correctness> {
+        match name {
+            "identity" => Ok(Correctness::Identity),
+            "positive_score" => Ok(Correctness::PositiveScore),
+            "identity_and_positive" => Ok(Correctness::IdentityAndPositive),
+            _ => Err(exceptions::PyValueError::new_err(format!(
+                "unknown correctness rule {:?}",
+                name
+            ))),
+        }
+    }
+
+    /// Whether an aligned pair scoring `score` is correct, given whether it is `identical`.
+    fn counts(self, identical: bool, score: isize) -> bool {
+        match self {
+            Correctness::Identity => identical,
+            Correctness::PositiveScore => score > 0,
+            Correctness::IdentityAndPositive => identical && score > 0,
+        }
+    }
+}
+
+fn global_alignment(
+    a: &[&str],
+    b: &[&str],
+    scorer: &Scorer,
+    objective: Option<SecondaryObjective>,
+    stats: &mut dp::Stats,
+) -> PyResult<Alignment> {
+    let interned = InternedScorer::new(scorer, a, b);
+    let score = |x: usize, y: usize| interned.compare(x, y);
+    let band = || dp::Band::full(a.len(), b.len());
+    let preferring = |order, stats: &mut dp::Stats| {
+        dp::global_preferring(a.len(), b.len(), &band(), scorer.gaps, order, stats, score)
+    };
+    match objective {
+        Some(SecondaryObjective::MinGapRuns) => Ok(dp::global_counting_runs(
+            a.len(),
+            b.len(),
+            scorer.gaps,
+            -1,
+            stats,
+            score,
+        )),
+        Some(SecondaryObjective::MaxGapRuns) => Ok(dp::global_counting_runs(
+            a.len(),
+            b.len(),
+            scorer.gaps,
+            1,
+            stats,
+            score,
+        )),
+        Some(SecondaryObjective::LeftmostGaps | SecondaryObjective::RightmostGaps)
+            if scorer.gaps.is_affine() =>
+        {
+            Err(exceptions::PyValueError::new_err(
+                "leftmost_gaps and rightmost_gaps do not support gap open scores",
+            ))
+        }
+        Some(SecondaryObjective::LeftmostGaps) => Ok(preferring(
+            [StepMask::ALIGN, StepMask::DELETE, StepMask::INSERT],
+            stats,
+        )),
+        Some(SecondaryObjective::RightmostGaps) => Ok(preferring(
+            [StepMask::DELETE, StepMask::INSERT, StepMask::ALIGN],
+            stats,
+        )),
+        None if scorer.gaps.is_affine() => Ok(dp::global_counting_runs(
+            a.len(),
+            b.len(),
+            scorer.gaps,
+            0,
+            stats,
+            score,
+        )),
+        None if !scorer.gaps.is_uniform() => Ok(preferring(dp::SEAL_ORDER, stats)),
+        None => {
+            let start = Instant::now();
+            let needleman_wunsch =
+                NeedlemanWunsch::new(scorer.mismatch_score, scorer.gaps.score, scorer.gaps.score);
+            let alignment_set: Result<AlignmentSet<InMemoryAlignmentMatrix>, _> =
+                AlignmentSet::new(a.len(), b.len(), needleman_wunsch, score);
+            stats.cells += (a.len() + 1) * (b.len() + 1);
+            stats.fill += start.elapsed();
+
+            match alignment_set {
+                Ok(ref alignment_set) => {
+                    let start = Instant::now();
+                    let alignment = alignment_set.global_alignment();
+                    stats.traceback += start.elapsed();
+                    Ok(alignment)
+                }
+                Err(error) => Err(exceptions::PyValueError::new_err(error)),
+            }
+        }
+    }
+}
+
+fn local_alignment(
+    a: &[&str],
+    b: &[&str],
+    scorer: &Scorer,
+    x_drop: Option<isize>,
+    stats: &mut dp::Stats,
+) -> Alignment {
+    let interned = InternedScorer::new(scorer, a, b);
+    let score = |x: usize, y: usize| interned.compare(x, y);
+    match x_drop {
+        Some(x_drop) => dp::local_x_drop(a.len(), b.len(), scorer.gaps.score, x_drop, stats, score),
+        None => dp::local(a.len(), b.len(), scorer.gaps.score, stats, score),
+    }
+}
+
+/// Returns the best local alignment score of two sequences and the `(x, y)` cell it ends at,
+/// i.e. after `x` tokens of `a` and `y` tokens of `b`, without tracing the alignment back
+#[pyfunction(match_score = 1, mismatch_score = -1, gap_score = -1)]
+fn local_max(
+    a: &PyAny,
+    b: &PyAny,
+    match_score: isize,
+    mismatch_score: isize,
+    gap_score: isize,
+    similarity_matrix: Option<SimilarityMatrix>,
+) -> PyResult<(isize, usize, usize)> {
+    let (a, b) = (tokens("a", a)?, tokens("b", b)?);
+    let matrix = similarity_matrix.unwrap_or_default();
+    let scorer = Scorer::new(
+        &matrix,
+        match_score,
+        mismatch_score,
+        dp::Gaps::linear(gap_score),
+        a.len(),
+        b.len(),
+    );
+    let interned = InternedScorer::new(&scorer, &a, &b);
+    let (score, end) = dp::local_max(a.len(), b.len(), gap_score, |x, y| interned.compare(x, y));
+    Ok((score, end.x, end.y))
+}
+
+/// Returns the percentage of columns aligning identical tokens in the best local alignment
+/// of two sequences, as `percent_identity` of a local `align` would, without building a result
+///
+/// Like `local_max`, only two rows of the fill are kept, so memory grows with `len(a)` alone,
+/// but carrying the counts along the fill takes about twice as long as a local `align`.
+#[pyfunction(match_score = 1, mismatch_score = -1, gap_score = -1)]
+fn local_identity(
+    a: &PyAny,
+    b: &PyAny,
+    match_score: isize,
+    mismatch_score: isize,
+    gap_score: isize,
+    similarity_matrix: Option<SimilarityMatrix>,
+) -> PyResult<f64> {
+    let (a, b) = (tokens("a", a)?, tokens("b", b)?);
+    let matrix = similarity_matrix.unwrap_or_default();
+    let scorer = Scorer::new(
+        &matrix,
+        match_score,
+        mismatch_score,
+        dp::Gaps::linear(gap_score),
+        a.len(),
+        b.len(),
+    );
+    let interned = InternedScorer::new(&scorer, &a, &b);
+    let (matches, columns) = dp::local_identity(
+        a.len(),
+        b.len(),
+        gap_score,
+        |x, y| interned.compare(x, y),
+        |x, y| interned.x_ids[x] == interned.y_ids[y],
+    );
+    if columns == 0 {
+        return Ok(0f64);
+    }
+    Ok(100f64 * matches as f64 / columns as f64)
+}
+
+/// Finds every placement of `pattern` within `text` scoring at least `min_score`, sorted by
+/// where they start in `text`
+///
+/// Each placement aligns all of `pattern` to a stretch of `text`. Placements are picked best
+/// first, skipping any that overlap a better one.
+#[pyfunction(match_score = 1, mismatch_score = -1, gap_score = -1)]
+#[allow(clippy::too_many_arguments)]
+fn find_all(
+    pattern: &PyAny,
+    text: &PyAny,
+    min_score: isize,
+    match_score: isize,
+    mismatch_score: isize,
+    gap_score: isize,
+    similarity_matrix: Option<SimilarityMatrix>,
+) -> PyResult<Vec<AlignmentResult>> {
+    let (pattern, text) = (tokens("pattern", pattern)?, tokens("text", text)?);
+    if pattern.is_empty() {
+        return Err(exceptions::PyValueError::new_err(
+            "pattern must not be empty",
+        ));
+    }
+    let matrix = similarity_matrix.unwrap_or_default();
+    let scorer = Scorer::new(
+        &matrix,
+        match_score,
+        mismatch_score,
+        dp::Gaps::linear(gap_score),
+        pattern.len(),
+        text.len(),
+    );
+    let interned = InternedScorer::new(&scorer, &pattern, &text);
+    let mut stats = dp::Stats::default();
+    let fit = dp::fit(pattern.len(), text.len(), gap_score, &mut stats, |x, y| {
+        interned.compare(x, y)
+    });
+
+    let mut ends: Vec<usize> = (0..=text.len())
+        .filter(|y_end| fit.scores()[*y_end] >= min_score)
+        .collect();
+    ends.sort_by_key(|y_end| (Reverse(fit.scores()[*y_end]), *y_end));
+    let mut placements: Vec<(usize, usize, Alignment)> = vec![];
+    for y_end in ends {
+        let overlaps = |start: usize, end: usize| {
+            placements
+                .iter()
+                .any(|(placed_start, placed_end, _)| start < *placed_end && *placed_start < end)
+        };
+        // Ending inside a better placement overlaps it, wherever the trace would start.
+        if y_end > 0 && overlaps(y_end - 1, y_end) {
+            continue;
+        }
+        let alignment = fit.alignment(y_end, &mut stats);
+        let y_start = alignment.origin().y;
+        if y_start < y_end && !overlaps(y_start, y_end) {
+            placements.push((y_start, y_end, alignment));
+        }
+    }
+    placements.sort_by_key(|(y_start, _, _)| *y_start);
+
+    Ok(placements
+        .iter()
+        .map(|(_, _, placement)| AlignmentResult {
+            cells_evaluated: stats.cells,
+            algorithm: Some("needleman_wunsch"),
+            ..AlignmentResult::from_alignment(&pattern, &text, &scorer, placement)
+        })
+        .collect())
+}
+
+/// Globally aligns `read` to a `consensus` containing ambiguity codes
+///
+/// A consensus token listed in `ambiguity` matches any of the tokens it maps to, e.g.
+/// `{"R": ["A", "G"]}` lets `R` match both `A` and `G`.
+#[pyfunction(match_score = 1, mismatch_score = -1, gap_score = -1)]
+fn align_to_consensus(
+    read: &PyAny,
+    consensus: &PyAny,
+    ambiguity: HashMap<String, Vec<String>>,
+    match_score: isize,
+    mismatch_score: isize,
+    gap_score: isize,
+    similarity_matrix: Option<SimilarityMatrix>,
+) -> PyResult<AlignmentResult> {
+    let (read, consensus) = (tokens("read", read)?, tokens("consensus", consensus)?);
+    let matrix = similarity_matrix.unwrap_or_default();
+    let scorer = Scorer {
+        ambiguity: Some(&ambiguity),
+        ..Scorer::new(
+            &matrix,
+            match_score,
+            mismatch_score,
+            dp::Gaps::linear(gap_score),
+            read.len(),
+            consensus.len(),
+        )
+    };
+
+    let alignment = global_alignment(&read, &consensus, &scorer, None, &mut dp::Stats::default())?;
+    Ok(AlignmentResult::from_alignment(
+        &read, &consensus, &scorer, &alignment,
+    ))
+}
+
+/// Globally aligns sequences of `(token, weight)` pairs, so that aligning important tokens
+/// counts for more
+///
+/// Aligning two tokens scores what `align` would score them, multiplied by the product of
+/// their weights and rounded to the nearest integer. Gaps are scored as usual, regardless of
+/// weight. Weights must be finite and not negative.
+#[pyfunction(match_score = 1, mismatch_score = -1, gap_score = -1)]
+fn align_weighted(
+    a: &PyAny,
+    b: &PyAny,
+    match_score: isize,
+    mismatch_score: isize,
+    gap_score: isize,
+    similarity_matrix: Option<SimilarityMatrix>,
+) -> PyResult<AlignmentResult> {
+    let (a, b) = (weighted_tokens("a", a)?, weighted_tokens("b", b)?);
+    if let Some((token, weight)) = a
+        .iter()
+        .chain(b.iter())
+        .find(|(_, weight)| !weight.is_finite() || *weight < 0f64)
+    {
+        return Err(exceptions::PyValueError::new_err(format!(
+            "token {:?} has invalid weight {}",
+            token, weight
+        )));
+    }
+    let (a, x_weights): (Vec<&str>, Vec<f64>) = a.into_iter().unzip();
+    let (b, y_weights): (Vec<&str>, Vec<f64>) = b.into_iter().unzip();
+
+    let matrix = similarity_matrix.unwrap_or_default();
+    let scorer = Scorer::new(
+        &matrix,
+        match_score,
+        mismatch_score,
+        dp::Gaps::linear(gap_score),
+        a.len(),
+        b.len(),
+    );
+    let interned = InternedScorer::new(&scorer, &a, &b);
+    let weighted = |x: usize, y: usize| match interned.compare(x, y) {
+        score if score == scorer.forbidden_score => score,
+        score => (score as f64 * x_weights[x] * y_weights[y]).round() as isize,
+    };
+
+    let mut stats = dp::Stats::default();
+    let alignment = dp::global(
+        a.len(),
+        b.len(),
+        &dp::Band::full(a.len(), b.len()),
+        scorer.gaps,
+        &mut stats,
+        weighted,
+    );
+    let mut result = AlignmentResult::from_alignment(&a, &b, &scorer, &alignment);
+    result.cells_evaluated = stats.cells;
+    result.algorithm = Some("needleman_wunsch");
+    // Weights are not negative, so pairs score most and least at the extreme weights.
+    let weight_bounds = |weights: &[f64]| weights.iter().copied().minmax().into_option();
+    if let (Some((x_min, x_max)), Some((y_min, y_max))) =
+        (weight_bounds(&x_weights), weight_bounds(&y_weights))
+    {
+        let (worst_pair, best_pair) = scorer.pair_bounds();
+        let scaled = |score: isize| {
+            [x_min * y_min, x_max * y_max].map(|weight| (score as f64 * weight).round() as isize)
+        };
+        let [low, high] = scaled(worst_pair);
+        let worst = low.min(high);
+        let [low, high] = scaled(best_pair);
+        result.score_bounds = scorer.bounds((worst, low.max(high)), a.len(), b.len(), true);
+    }
+    for (step, score) in result.steps.iter().zip(result.column_scores.iter_mut()) {
+        if let Step::Align { x, y } = *step {
+            *score = weighted(x, y);
+        }
+    }
+    result.similarity_score = score_similarity(&alignment, |x, y| {
+        if scorer.matches(a[x], b[y]) {
+            Some(weighted(x, y))
+        } else {
+            None
+        }
+    });
+    Ok(result)
+}
+
+/// Splits the gapped rows of an alignment into its sequences and its steps, raising
+/// `ValueError` if the rows differ in length or a column gaps both of them
+fn ungap<'a>(
+    aligned_a: &[&'a str],
+    aligned_b: &[&'a str],
+    gap_symbol: &str,
+) -> PyResult<(Vec<&'a str>, Vec<&'a str>, Vec<StepMask>)> {
+    if aligned_a.len() != aligned_b.len() {
+        return Err(exceptions::PyValueError::new_err(format!(
+            "aligned rows differ in length: {} and {}",
+            aligned_a.len(),
+            aligned_b.len()
+        )));
+    }
+    let mut masks = vec![];
+    for (column, (x, y)) in aligned_a.iter().zip(aligned_b.iter()).enumerate() {
+        masks.push(match (*x == gap_symbol, *y == gap_symbol) {
+            (false, false) => StepMask::ALIGN,
+            (false, true) => StepMask::DELETE,
+            (true, false) => StepMask::INSERT,
+            (true, true) => {
+                return Err(exceptions::PyValueError::new_err(format!(
+                    "column {} is a gap in both rows",
+                    column
+                )))
+            }
+        });
+    }
+    let a = aligned_a
+        .iter()
+        .copied()
+        .filter(|x| *x != gap_symbol)
+        .collect();
+    let b = aligned_b
+        .iter()
+        .copied()
+        .filter(|y| *y != gap_symbol)
+        .collect();
+    Ok((a, b, masks))
+}
+
+/// Scores an existing alignment, given as the gapped rows of `a` and `b`, as `align` would
+/// have scored it
+///
+/// Tokens equal to `gap_symbol` are gaps. The rows must be of equal length, and no column
+/// may gap both of them.
+#[pyfunction(
+    match_score = 1,
+    mismatch_score = -1,
+    gap_score = -1,
+    matrix_is_authoritative = false,
+    gap_symbol = "\"-\""
+)]
+#[allow(clippy::too_many_arguments)]
+fn score_alignment(
+    aligned_a: &PyAny,
+    aligned_b: &PyAny,
+    match_score: isize,
+    mismatch_score: isize,
+    gap_score: isize,
+    similarity_matrix: Option<SimilarityMatrix>,
+    matrix_is_authoritative: bool,
+    end_gap_score: Option<isize>,
+    gap_symbol: &str,
+) -> PyResult<AlignmentResult> {
+    let (aligned_a, aligned_b) = (
+        tokens("aligned_a", aligned_a)?,
+        tokens("aligned_b", aligned_b)?,
+    );
+    let (a, b, masks) = ungap(&aligned_a, &aligned_b, gap_symbol)?;
+
+    let end_score = end_gap_score.unwrap_or(gap_score);
+    let gaps = dp::Gaps {
+        end_score,
+        end_open_score: end_score,
+        ..dp::Gaps::linear(gap_score)
+    };
+    let matrix_is_authoritative = matrix_is_authoritative && similarity_matrix.is_some();
+    let matrix = similarity_matrix.unwrap_or_default();
+    let scorer = Scorer {
+        matrix_is_authoritative,
+        ..Scorer::new(&matrix, match_score, mismatch_score, gaps, a.len(), b.len())
+    };
+
+    let origin = Cursor { x: 0, y: 0 };
+    let score = column_scores(&a, &b, &scorer, &Alignment::new(origin, masks.clone(), 0))
+        .iter()
+        .sum();
+    let alignment = Alignment::new(origin, masks, score);
+    Ok(AlignmentResult::from_alignment(&a, &b, &scorer, &alignment))
+}
+
+/// Returns the reverse complement of a DNA sequence of `A`, `C`, `G`, `T` and `N` tokens in
+/// either case
+#[pyfunction]
+fn reverse_complement(seq: &PyAny) -> PyResult<Vec<String>> {
+    complement_tokens(&tokens("seq", seq)?)
+}
+
+fn complement_tokens(seq: &[&str]) -> PyResult<Vec<String>> {
+    seq.iter()
+        .rev()
+        .map(|token| {
+            let complement = match *token {
+                "A" => "T",
+                "C" => "G",
+                "G" => "C",
+                "T" => "A",
+                "N" => "N",
+                "a" => "t",
+                "c" => "g",
+                "g" => "c",
+                "t" => "a",
+                "n" => "n",
+                _ => {
+                    return Err(exceptions::PyValueError::new_err(format!(
+                        "cannot complement token {:?}",
+                        token
+                    )))
+                }
+            };
+            Ok(complement.to_string())
+        })
+        .collect()
+}
+
+/// `(x_start, x_end, y_start, y_end, gain)` of an inverted segment
+type Inversion = (usize, usize, usize, usize, isize);
+
+/// Finds segments of `a` that align better to the reverse complement of `b` than to `b`
+///
+/// `a` and `b` are globally aligned, and each stretch between exactly matching runs of at
+/// least `min_length` tokens is re-aligned against the reverse complement of its part of
+/// `b`. Stretches at least `min_length` tokens long in both sequences whose score improves
+/// by at least `min_gain` are reported.
+#[pyfunction(min_length = 8, min_gain = 4, match_score = 1, mismatch_score = -1, gap_score = -1)]
+fn find_inversions(
+    a: &PyAny,
+    b: &PyAny,
+    min_length: usize,
+    min_gain: isize,
+    match_score: isize,
+    mismatch_score: isize,
+    gap_score: isize,
+) -> PyResult<Vec<Inversion>> {
+    let (a, b) = (tokens("a", a)?, tokens("b", b)?);
+    if min_length == 0 {
+        return Err(exceptions::PyValueError::new_err(
+            "min_length must be positive",
+        ));
+    }
+
+    let matrix = HashMap::new();
+    let scorer = Scorer::new(
+        &matrix,
+        match_score,
+        mismatch_score,
+        dp::Gaps::linear(gap_score),
+        a.len(),
+        b.len(),
+    );
+    let alignment = global_alignment(&a, &b, &scorer, None, &mut dp::Stats::default())?;
+    let anchors =
+        AlignmentResult::from_alignment(&a, &b, &scorer, &alignment).matching_runs(min_length)?;
+
+    let segment_score = |x_seg: &[&str], y_seg: &[&str]| {
+        let band = dp::Band::full(x_seg.len(), y_seg.len());
+        dp::global(
+            x_seg.len(),
+            y_seg.len(),
+            &band,
+            scorer.gaps,
+            &mut dp::Stats::default(),
+            |x, y| scorer.compare(x_seg[x], y_seg[y]),
+        )
+        .score()
+    };
+
+    let mut inversions = vec![];
+    for (before, after) in anchors.iter().tuple_windows() {
+        let (x_start, y_start) = (before.0 + before.2, before.1 + before.2);
+        let (x_end, y_end) = (after.0, after.1);
+        if x_end - x_start < min_length || y_end - y_start < min_length {
+            continue;
+        }
+
+        let (x_seg, y_seg) = (&a[x_start..x_end], &b[y_start..y_end]);
+        let reversed = complement_tokens(y_seg)?;
+        let reversed: Vec<&str> = reversed.iter().map(String::as_str).collect();
+        let gain = segment_score(x_seg, &reversed) - segment_score(x_seg, y_seg);
+        if gain >= min_gain {
+            inversions.push((x_start, x_end, y_start, y_end, gain));
+        }
+    }
+    Ok(inversions)
+}
+
+/// Finds non-overlapping repeats within a sequence, best first
+///
+/// The best local alignment of the sequence against itself is reported and the block of
+/// pairs it spans masked out, until no remaining alignment scores at least `min_score`.
+#[pyfunction(match_score = 1, mismatch_score = -1, gap_score = -1)]
+fn find_repeats(
+    seq: &PyAny,
+    min_score: isize,
+    match_score: isize,
+    mismatch_score: isize,
+    gap_score: isize,
+    similarity_matrix: Option<SimilarityMatrix>,
+) -> PyResult<Vec<AlignmentResult>> {
+    let seq = tokens("seq", seq)?;
+    if min_score <= 0 {
+        return Err(exceptions::PyValueError::new_err(
+            "min_score must be positive",
+        ));
+    }
+
+    let matrix = similarity_matrix.unwrap_or_default();
+    let scorer = Scorer::new(
+        &matrix,
+        match_score,
+        mismatch_score,
+        dp::Gaps::linear(gap_score),
+        seq.len(),
+        seq.len(),
+    );
+
+    let mut masked: Vec<(Cursor, Cursor)> = vec![];
+    let mut repeats = vec![];
+    let mut stats = dp::Stats::default();
+    loop {
+        let alignment = dp::local(
+            seq.len(),
+            seq.len(),
+            scorer.gaps.score,
+            &mut stats,
+            |x, y| {
+                let is_masked = masked.iter().any(|(start, end)| {
+                    (start.x..end.x).contains(&x) && (start.y..end.y).contains(&y)
+                });
+                if x >= y || is_masked {
+                    scorer.forbidden_score
+                } else {
+                    scorer.compare(seq[x], seq[y])
+                }
+            },
+        );
+        if alignment.is_empty() || alignment.score() < min_score {
+            break;
+        }
+
+        let repeat = AlignmentResult::from_alignment(&seq, &seq, &scorer, &alignment);
+        masked.push((
+            Cursor {
+                x: repeat.x_start,
+                y: repeat.y_start,
+            },
+            Cursor {
+                x: repeat.x_end,
+                y: repeat.y_end,
+            },
+        ));
+        repeats.push(repeat);
+    }
+    Ok(repeats)
+}
+
+/// Aligned columns of two sequences, produced one at a time
+#[pyclass]
+struct AlignmentColumns {
+    x_seq: Vec<String>,
+    y_seq: Vec<String>,
+    masks: std::vec::IntoIter<StepMask>,
+    cursor: Cursor,
+}
+
+#[pymethods]
+impl AlignmentColumns {
+    fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<Self>) -> Option<(String, String)> {
+        let mask = slf.masks.next()?;
+        let Cursor { x, y } = slf.cursor;
+        slf.cursor.apply_forwards_step(mask);
+        let step = match mask {
+            StepMask::ALIGN => Step::Align { x, y },
+            StepMask::DELETE => Step::Delete { x },
+            _ => Step::Insert { y },
+        };
+        Some(column(&slf.x_seq, &slf.y_seq, &GapSymbols::default(), step))
+    }
+}
+
+/// Like `align`, but returns an iterator over the aligned columns instead of a result
+#[pyfunction(
+    match_score = 1,
+    mismatch_score = -1,
+    gap_score = -1,
+    matrix_is_authoritative = false
+)]
+fn align_columns_iter(
+    a: &PyAny,
+    b: &PyAny,
+    match_score: isize,
+    mismatch_score: isize,
+    gap_score: isize,
+    similarity_matrix: Option<SimilarityMatrix>,
+    matrix_is_authoritative: bool,
+) -> PyResult<AlignmentColumns> {
+    let (a, b) = (tokens("a", a)?, tokens("b", b)?);
+    let matrix_is_authoritative = matrix_is_authoritative && similarity_matrix.is_some();
+    let matrix = similarity_matrix.unwrap_or_default();
+    let scorer = Scorer {
+        matrix_is_authoritative,
+        ..Scorer::new(
+            &matrix,
+            match_score,
+            mismatch_score,
+            dp::Gaps::linear(gap_score),
+            a.len(),
+            b.len(),
+        )
+    };
+
+    let global_alignment = global_alignment(&a, &b, &scorer, None, &mut dp::Stats::default())?;
+    Ok(AlignmentColumns {
+        x_seq: a.iter().map(|token| token.to_string()).collect(),
+        y_seq: b.iter().map(|token| token.to_string()).collect(),
+        masks: global_alignment
+            .steps()
+            .map(|step| step.mask())
+            .collect::<Vec<_>>()
+            .into_iter(),
+        cursor: *global_alignment.origin(),
+    })
+}
+
+/// Heuristically aligns two long, globally similar sequences in overlapping windows
+///
+/// Each window globally aligns the next `window` tokens of both sequences, but only keeps its
+/// columns up to the last identical pair before either sequence is `window - overlap` tokens
+/// in, and the next window starts from there. Only windows of `window` squared cells are
+/// filled, but an optimal gap longer than about `overlap` tokens can be missed, so the score
+/// may fall short of that of `align`. The result is not guaranteed to be optimal, but on
+/// sequences differing by edits each much shorter than `overlap`, it scores within 1% of it.
+#[pyfunction(match_score = 1, mismatch_score = -1, gap_score = -1)]
+#[allow(clippy::too_many_arguments)]
+fn windowed_align(
+    a: &PyAny,
+    b: &PyAny,
+    window: usize,
+    overlap: usize,
+    match_score: isize,
+    mismatch_score: isize,
+    gap_score: isize,
+    similarity_matrix: Option<SimilarityMatrix>,
+) -> PyResult<AlignmentResult> {
+    let (a, b) = (tokens("a", a)?, tokens("b", b)?);
+    if overlap >= window {
+        return Err(exceptions::PyValueError::new_err(format!(
+            "overlap {} must be less than window {}",
+            overlap, window
+        )));
+    }
+
+    let matrix = similarity_matrix.unwrap_or_default();
+    let scorer = Scorer::new(
+        &matrix,
+        match_score,
+        mismatch_score,
+        dp::Gaps::linear(gap_score),
+        a.len(),
+        b.len(),
+    );
+    let interned = InternedScorer::new(&scorer, &a, &b);
+    let mut stats = dp::Stats::default();
+    let mut steps = vec![];
+    let mut cursor = Cursor { x: 0, y: 0 };
+    while cursor.x < a.len() || cursor.y < b.len() {
+        let x_len = (a.len() - cursor.x).min(window);
+        let y_len = (b.len() - cursor.y).min(window);
+        let origin = cursor;
+        let aligned = dp::global(
+            x_len,
+            y_len,
+            &dp::Band::full(x_len, y_len),
+            scorer.gaps,
+            &mut stats,
+            |x, y| interned.compare(origin.x + x, origin.y + y),
+        );
+        let masks: Vec<StepMask> = aligned.steps().map(|step| step.mask()).collect();
+        if cursor.x + x_len == a.len() && cursor.y + y_len == b.len() {
+            steps.extend(masks);
+            break;
+        }
+
+        let keep = window - overlap;
+        let mut end = *aligned.origin();
+        let mut kept = 0;
+        let mut last_match = None;
+        for (i, mask) in masks.iter().enumerate() {
+            if end.x >= keep || end.y >= keep {
+                break;
+            }
+            if *mask == StepMask::ALIGN && a[origin.x + end.x] == b[origin.y + end.y] {
+                last_match = Some(i);
+            }
+            end.apply_forwards_step(*mask);
+            kept = i + 1;
+        }
+        let kept = last_match.map_or(kept, |i| i + 1);
+        for mask in &masks[..kept] {
+            cursor.apply_forwards_step(*mask);
+        }
+        steps.extend_from_slice(&masks[..kept]);
+    }
+
+    let origin = Cursor { x: 0, y: 0 };
+    let score = column_scores(&a, &b, &scorer, &Alignment::new(origin, steps.clone(), 0))
+        .iter()
+        .sum();
+    let alignment = Alignment::new(origin, steps, score);
+    Ok(AlignmentResult {
+        cells_evaluated: stats.cells,
+        algorithm: Some("needleman_wunsch"),
+        ..AlignmentResult::from_alignment(&a, &b, &scorer, &alignment)
+    })
+}
+
+/// Globally aligns two very long sequences like `align`, in memory that grows with the
+/// square root of the length of `b` rather than with it
+///
+/// Only every `checkpoint_stride`th row of the fill is kept, each row holding `len(a) + 1`
+/// scores, and the rows between two checkpoints are filled again while tracing back through
+/// them. At most `len(b) // checkpoint_stride + checkpoint_stride + 3` rows are held at once,
+/// which is least with `checkpoint_stride` near the square root of `len(b)`, and every row is
+/// filled twice, as `cells_evaluated` shows. The alignment is the one `align` finds.
+#[pyfunction(match_score = 1, mismatch_score = -1, gap_score = -1)]
+fn align_checkpointed(
+    a: &PyAny,
+    b: &PyAny,
+    checkpoint_stride: usize,
+    match_score: isize,
+    mismatch_score: isize,
+    gap_score: isize,
+    similarity_matrix: Option<SimilarityMatrix>,
+) -> PyResult<AlignmentResult> {
+    let (a, b) = (tokens("a", a)?, tokens("b", b)?);
+    if checkpoint_stride == 0 {
+        return Err(exceptions::PyValueError::new_err(
+            "checkpoint_stride must be positive",
+        ));
+    }
+
+    let matrix = similarity_matrix.unwrap_or_default();
+    let scorer = Scorer::new(
+        &matrix,
+        match_score,
+        mismatch_score,
+        dp::Gaps::linear(gap_score),
+        a.len(),
+        b.len(),
+    );
+    let interned = InternedScorer::new(&scorer, &a, &b);
+    let mut stats = dp::Stats::default();
+    let alignment = dp::global_checkpointed(
+        a.len(),
+        b.len(),
+        scorer.gaps,
+        checkpoint_stride,
+        &mut stats,
+        |x, y| interned.compare(x, y),
+    );
+    Ok(AlignmentResult {
+        cells_evaluated: stats.cells,
+        algorithm: Some("needleman_wunsch"),
+        ..AlignmentResult::from_alignment(&a, &b, &scorer, &alignment)
+    })
+}
+
+/// Heuristically aligns two long, mostly similar sequences
+///
+/// Exact matches of at least `kmer_size` tokens are chained into anchors, and only the
+/// stretches between anchors are aligned, within `band` cells of their diagonal. The result
+/// is not guaranteed to be optimal.
+#[pyfunction(kmer_size = 8, band = 16, match_score = 1, mismatch_score = -1, gap_score = -1)]
+#[allow(clippy::too_many_arguments)]
+fn seed_and_extend(
+    a: &PyAny,
+    b: &PyAny,
+    kmer_size: usize,
+    band: usize,
+    match_score: isize,
+    mismatch_score: isize,
+    gap_score: isize,
+    similarity_matrix: Option<SimilarityMatrix>,
+) -> PyResult<AlignmentResult> {
+    let (a, b) = (tokens("a", a)?, tokens("b", b)?);
+    if kmer_size == 0 {
+        return Err(exceptions::PyValueError::new_err(
+            "kmer_size must be positive",
+        ));
+    }
+
+    let matrix = similarity_matrix.unwrap_or_default();
+    let scorer = Scorer::new(
+        &matrix,
+        match_score,
+        mismatch_score,
+        dp::Gaps::linear(gap_score),
+        a.len(),
+        b.len(),
+    );
+    let end = seeds::Anchor {
+        x: a.len(),
+        y: b.len(),
+        len: 0,
+    };
+
+    let mut stats = dp::Stats::default();
+    let mut steps = vec![];
+    let mut score = 0;
+    let mut cursor = Cursor { x: 0, y: 0 };
+    for anchor in seeds::chain(seeds::anchors(&a, &b, kmer_size))
+        .into_iter()
+        .chain([end])
+    {
+        let (x_len, y_len) = (anchor.x - cursor.x, anchor.y - cursor.y);
+        let gap = dp::global(
+            x_len,
+            y_len,
+            &dp::Band::diagonal(x_len, y_len, band),
+            scorer.gaps,
+            &mut stats,
+            |x, y| scorer.compare(a[cursor.x + x], b[cursor.y + y]),
+        );
+        steps.extend(gap.steps().map(|step| step.mask()));
+        score += gap.score();
+
+        for offset in 0..anchor.len {
+            steps.push(StepMask::ALIGN);
+            score += scorer.compare(a[anchor.x + offset], b[anchor.y + offset]);
+        }
+        cursor = Cursor {
+            x: anchor.x + anchor.len,
+            y: anchor.y + anchor.len,
+        };
+    }
+
+    let alignment = Alignment::new(Cursor { x: 0, y: 0 }, steps, score);
+    Ok(AlignmentResult {
+        cells_evaluated: stats.cells,
+        algorithm: Some("needleman_wunsch"),
+        ..AlignmentResult::from_alignment(&a, &b, &scorer, &alignment)
+    })
+}
+
+/// Globally realigns `a` and `b` optimally within `band` cells of the path of `prior`, a
+/// global alignment of the same sequences such as a heuristic one
+///
+/// Only cells within `band` rows and columns of a column of `prior` are filled, so the
+/// result scores at least as well as `prior` under the same scores, but an optimal alignment
+/// straying further from `prior` than that is not found.
+#[pyfunction(match_score = 1, mismatch_score = -1, gap_score = -1)]
+#[allow(clippy::too_many_arguments)]
+fn refine(
+    a: &PyAny,
+    b: &PyAny,
+    prior: PyRef<AlignmentResult>,
+    band: usize,
+    match_score: isize,
+    mismatch_score: isize,
+    gap_score: isize,
+    similarity_matrix: Option<SimilarityMatrix>,
+) -> PyResult<AlignmentResult> {
+    let (a, b) = (tokens("a", a)?, tokens("b", b)?);
+    if prior.x_seq != a || prior.y_seq != b {
+        return Err(exceptions::PyValueError::new_err(
+            "prior does not align a and b",
+        ));
+    }
+    if (prior.x_start, prior.x_end, prior.y_start, prior.y_end) != (0, a.len(), 0, b.len()) {
+        return Err(exceptions::PyValueError::new_err(
+            "prior must be a global alignment",
+        ));
+    }
+    prior.require_contiguous("refine")?;
+
+    let matrix = similarity_matrix.unwrap_or_default();
+    let scorer = Scorer::new(
+        &matrix,
+        match_score,
+        mismatch_score,
+        dp::Gaps::linear(gap_score),
+        a.len(),
+        b.len(),
+    );
+    let interned = InternedScorer::new(&scorer, &a, &b);
+    let masks: Vec<StepMask> = prior.steps.iter().map(|step| step.mask()).collect();
+    let mut stats = dp::Stats::default();
+    let alignment = dp::global(
+        a.len(),
+        b.len(),
+        &dp::Band::around(a.len(), b.len(), &masks, band),
+        scorer.gaps,
+        &mut stats,
+        |x, y| interned.compare(x, y),
+    );
+    Ok(AlignmentResult {
+        cells_evaluated: stats.cells,
+        algorithm: Some("needleman_wunsch"),
+        ..AlignmentResult::from_alignment(&a, &b, &scorer, &alignment)
+    })
+}
+
+/// Alignment of a sequence to a path through a partial order graph
+#[pyclass]
+struct PartialOrderAlignmentResult {
+    /// Ids of the nodes of the path, in order
+    #[pyo3(get)]
+    path: Vec<usize>,
+    /// Columns of node and sequence tokens, with `"-"` marking gaps
+    #[pyo3(get)]
+    alignments: Vec<(String, String)>,
+    #[pyo3(get)]
+    alignment_score: isize,
+}
+
+/// Globally aligns `seq` to the best path through a partial order graph of `nodes`, given
+/// as `(id, token)` pairs, and `edges` between their ids, as partial order alignment does
+///
+/// The graph must be acyclic. The path may start at any node without incoming edges and end
+/// at any node without outgoing ones, and gaps score `gap_score` whether they skip a node or
+/// a token of `seq`.
+#[pyfunction(match_score = 1, mismatch_score = -1, gap_score = -1)]
+#[allow(clippy::too_many_arguments)]
+fn align_to_poa(
+    seq: &PyAny,
+    nodes: Vec<(usize, String)>,
+    edges: Vec<(usize, usize)>,
+    match_score: isize,
+    mismatch_score: isize,
+    gap_score: isize,
+    similarity_matrix: Option<SimilarityMatrix>,
+) -> PyResult<PartialOrderAlignmentResult> {
+    let seq = tokens("seq", seq)?;
+    let mut indices = HashMap::new();
+    for (index, (id, _)) in nodes.iter().enumerate() {
+        if indices.insert(*id, index).is_some() {
+            return Err(exceptions::PyValueError::new_err(format!(
+                "duplicate node id {}",
+                id
+            )));
+        }
+    }
+    let mut successors = vec![vec![]; nodes.len()];
+    let mut in_degrees = vec![0usize; nodes.len()];
+    for (from, to) in edges.iter() {
+        match (indices.get(from), indices.get(to)) {
+            (Some(&from), Some(&to)) => {
+                successors[from].push(to);
+                in_degrees[to] += 1;
+            }
+            _ => {
+                return Err(exceptions::PyValueError::new_err(format!(
+                    "edge ({}, {}) joins an unknown node",
+                    from, to
+                )))
+            }
+        }
+    }
+
+    // Kahn's algorithm, keeping the given order among nodes that are ready together.
+    let mut order = Vec::with_capacity(nodes.len());
+    let mut ready: std::collections::VecDeque<usize> = (0..nodes.len())
+        .filter(|&node| in_degrees[node] == 0)
+        .collect();
+    while let Some(node) = ready.pop_front() {
+        order.push(node);
+        for &successor in &successors[node] {
+            in_degrees[successor] -= 1;
+            if in_degrees[successor] == 0 {
+                ready.push_back(successor);
+            }
+        }
+    }
+    if order.len() < nodes.len() {
+        return Err(exceptions::PyValueError::new_err(
+            "edges must not form a cycle",
+        ));
+    }
+    let mut positions = vec![0; nodes.len()];
+    for (position, &node) in order.iter().enumerate() {
+        positions[node] = position;
+    }
+    let mut predecessors = vec![vec![]; nodes.len()];
+    for (node, node_successors) in successors.iter().enumerate() {
+        for &successor in node_successors {
+            predecessors[positions[successor]].push(positions[node]);
+        }
+    }
+
+    let tokens: Vec<&str> = order.iter().map(|&node| nodes[node].1.as_str()).collect();
+    let matrix = similarity_matrix.unwrap_or_default();
+    let scorer = Scorer::new(
+        &matrix,
+        match_score,
+        mismatch_score,
+        dp::Gaps::linear(gap_score),
+        tokens.len(),
+        seq.len(),
+    );
+    let alignment = dp::partial_order(
+        &predecessors,
+        seq.len(),
+        gap_score,
+        &mut dp::Stats::default(),
+        |node, y| scorer.compare(tokens[node], seq[y]),
+    );
+    let token = |tokens: &[&str], index: Option<usize>| {
+        index.map_or_else(|| String::from(GAP), |index| tokens[index].to_string())
+    };
+    Ok(PartialOrderAlignmentResult {
+        path: alignment
+            .columns
+            .iter()
+            .filter_map(|(node, _)| node.map(|node| nodes[order[node]].0))
+            .collect(),
+        alignments: alignment
+            .columns
+            .iter()
+            .map(|(node, y)| (token(&tokens, *node), token(&seq, *y)))
+            .collect(),
+        alignment_score: alignment.score,
+    })
+}
+
+#[pyclass]
+struct ObjectAlignmentResult {
+    #[pyo3(get)]
+    alignments: Vec<(PyObject, PyObject)>,
+    #[pyo3(get)]
+    alignment_score: isize,
+    #[pyo3(get)]
+    similarity_score: f64,
+}
+
+/// Finds alignment similarity between two sequences of arbitrary Python objects
+///
+/// Objects are compared with `==`, or with `eq_fn(x, y)` if given. Gapped positions are
+/// filled with `gap`, which defaults to `"-"`.
+#[pyfunction(match_score = 1, mismatch_score = -1, gap_score = -1)]
+#[allow(clippy::too_many_arguments)]
+fn align_objects(
+    py: Python,
+    a: Vec<PyObject>,
+    b: Vec<PyObject>,
+    eq_fn: Option<PyObject>,
+    match_score: isize,
+    mismatch_score: isize,
+    gap_score: isize,
+    gap: Option<PyObject>,
+) -> PyResult<ObjectAlignmentResult> {
+    let mut equal = vec![vec![false; b.len()]; a.len()];
+    for (x, x_object) in a.iter().enumerate() {
+        for (y, y_object) in b.iter().enumerate() {
+            equal[x][y] = match eq_fn {
+                Some(ref eq_fn) => eq_fn.call1(py, (x_object, y_object))?.is_true(py)?,
+                None => x_object.as_ref(py).eq(y_object)?,
+            };
+        }
+    }
+
+    let needleman_wunsch = NeedlemanWunsch::new(mismatch_score, gap_score, gap_score);
+    let alignment_set: Result<AlignmentSet<InMemoryAlignmentMatrix>, _> =
+        AlignmentSet::new(a.len(), b.len(), needleman_wunsch, |x, y| {
+            if equal[x][y] {
+                match_score
+            } else {
+                mismatch_score
+            }
+        });
+
+    match alignment_set {
+        Ok(ref alignment_set) => {
+            let global_alignment = alignment_set.global_alignment();
+            let gap = gap.unwrap_or_else(|| "-".into_py(py));
+            Ok(ObjectAlignmentResult {
+                alignments: global_alignment
+                    .steps()
+                    .map(|step| match step {
+                        Step::Align { x, y } => (a[x].clone_ref(py), b[y].clone_ref(py)),
+                        Step::Delete { x } => (a[x].clone_ref(py), gap.clone_ref(py)),
+                        Step::Insert { y } => (gap.clone_ref(py), b[y].clone_ref(py)),
+                    })
+                    .collect(),
+                alignment_score: global_alignment.score(),
+                similarity_score: score_similarity(&global_alignment, |x, y| {
+                    if equal[x][y] {
+                        Some(match_score)
+                    } else {
+                        None
+                    }
+                }),
+            })
+        }
+        Err(error) => Err(exceptions::PyValueError::new_err(error)),
+    }
+}
+
+/// Counts, for each position of a shared reference `b`, the query tokens aligned to it
+/// across `refs_query_results`, with gaps counted under `gap_key`
+#[pyfunction(gap_key = "\"gap\"")]
+fn position_frequencies(
+    refs_query_results: Vec<PyRef<AlignmentResult>>,
+    reference_len: usize,
+    gap_key: &str,
+) -> PyResult<Vec<HashMap<String, usize>>> {
+    let mut frequencies = vec![HashMap::new(); reference_len];
+    for result in refs_query_results.iter() {
+        if result.y_seq.len() != reference_len {
+            return Err(exceptions::PyValueError::new_err(format!(
+                "result aligned to a reference of length {}, expected {}",
+                result.y_seq.len(),
+                reference_len
+            )));
+        }
+        for step in result.steps.iter() {
+            let (token, y) = match *step {
+                Step::Align { x, y } => (result.x_seq[x].as_str(), y),
+                Step::Insert { y } => (gap_key, y),
+                Step::Delete { .. } => continue,
+            };
+            *frequencies[y].entry(token.to_string()).or_insert(0) += 1;
+        }
+    }
+    Ok(frequencies)
+}
+
+/// Counts how often each token of `a` is aligned to each different token of `b` across
+/// `results`, keyed by the `(a, b)` pair
+#[pyfunction]
+fn confusion_matrix(results: Vec<PyRef<AlignmentResult>>) -> HashMap<(String, String), usize> {
+    let mut confusions = HashMap::new();
+    for result in results.iter() {
+        for (column, step) in result.steps.iter().enumerate() {
+            if let Step::Align { x, y } = *step {
+                if result.operation(column) == "replace" {
+                    let pair = (result.x_seq[x].clone(), result.y_seq[y].clone());
+                    *confusions.entry(pair).or_insert(0) += 1;
+                }
+            }
+        }
+    }
+    confusions
+}
+
+/// Combines alignments of `a` to `b` and of `b` to `c` into rows for `a`, `b` and `c`
+///
+/// Gaps are added wherever either alignment puts a token against a gap in `b`, so that the
+/// three rows have equal length. Both alignments must span all of `b`.
+#[pyfunction]
+fn merge_via_common(
+    ab: PyRef<AlignmentResult>,
+    bc: PyRef<AlignmentResult>,
+) -> PyResult<Vec<Vec<String>>> {
+    if ab.y_seq != bc.x_seq {
+        return Err(exceptions::PyValueError::new_err(
+            "alignments do not share a common sequence",
+        ));
+    }
+    ab.require_contiguous("merge_via_common")?;
+    bc.require_contiguous("merge_via_common")?;
+    let b_len = ab.y_seq.len();
+    if (ab.y_start, ab.y_end) != (0, b_len) || (bc.x_start, bc.x_end) != (0, b_len) {
+        return Err(exceptions::PyValueError::new_err(
+            "alignments must span all of the common sequence",
+        ));
+    }
+
+    let token = |seq: &[String], index: Option<usize>| match index {
+        Some(index) => seq[index].clone(),
+        None => String::from(GAP),
+    };
+    let mut rows = vec![vec![], vec![], vec![]];
+    let mut push = |a: Option<usize>, b: Option<usize>, c: Option<usize>| {
+        rows[0].push(token(&ab.x_seq, a));
+        rows[1].push(token(&ab.y_seq, b));
+        rows[2].push(token(&bc.y_seq, c));
+    };
+
+    let (mut ab_steps, mut bc_steps) = (ab.steps.iter().peekable(), bc.steps.iter().peekable());
+    loop {
+        match (ab_steps.peek(), bc_steps.peek()) {
+            (Some(Step::Delete { x }), _) => {
+                push(Some(*x), None, None);
+                ab_steps.next();
+            }
+            (_, Some(Step::Insert { y })) => {
+                push(None, None, Some(*y));
+                bc_steps.next();
+            }
+            (Some(ab_step), Some(bc_step)) => {
+                let (a, b) = match **ab_step {
+                    Step::Align { x, y } => (Some(x), y),
+                    Step::Insert { y } => (None, y),
+                    Step::Delete { .. } => unreachable!(),
+                };
+                let c = match **bc_step {
+                    Step::Align { y, .. } => Some(y),
+                    _ => None,
+                };
+                push(a, Some(b), c);
+                ab_steps.next();
+                bc_steps.next();
+            }
+            (None, None) => break,
+            _ => unreachable!("both alignments span the common sequence"),
+        }
+    }
+    Ok(rows)
+}
+
+/// Generates `length` tokens drawn uniformly from `alphabet`, always the same for a `seed`
+#[pyfunction]
+fn random_sequence(length: usize, alphabet: &PyAny, seed: u64) -> PyResult<Vec<String>> {
+    let alphabet = tokens("alphabet", alphabet)?;
+    if alphabet.is_empty() && length > 0 {
+        return Err(exceptions::PyValueError::new_err(
+            "alphabet must not be empty",
+        ));
+    }
+    let mut rng = random::SplitMix64::new(seed);
+    Ok((0..length)
+        .map(|_| alphabet[rng.below(alphabet.len())].to_string())
+        .collect())
+}
+
+/// Returns the mean and standard deviation of the scores of globally aligning `seq` against
+/// `shuffles` shuffled copies of itself, for calibrating score thresholds
+///
+/// The shuffles are always the same for a `seed`, so the result is reproducible, including
+/// with `parallel`, which aligns them on all available cores.
+#[pyfunction(
+    seed = 0,
+    parallel = false,
+    match_score = 1,
+    mismatch_score = -1,
+    gap_score = -1
+)]
+#[allow(clippy::too_many_arguments)]
+fn background_score(
+    py: Python,
+    seq: &PyAny,
+    shuffles: usize,
+    seed: u64,
+    parallel: bool,
+    match_score: isize,
+    mismatch_score: isize,
+    gap_score: isize,
+    similarity_matrix: Option<SimilarityMatrix>,
+) -> PyResult<(f64, f64)> {
+    let seq = tokens("seq", seq)?;
+    if shuffles == 0 {
+        return Err(exceptions::PyValueError::new_err(
+            "shuffles must be positive",
+        ));
+    }
+
+    let matrix = similarity_matrix.unwrap_or_default();
+    let scorer = Scorer::new(
+        &matrix,
+        match_score,
+        mismatch_score,
+        dp::Gaps::linear(gap_score),
+        seq.len(),
+        seq.len(),
+    );
+    let mut rng = random::SplitMix64::new(seed);
+    let mut shuffled = seq.clone();
+    let copies: Vec<Vec<&str>> = (0..shuffles)
+        .map(|_| {
+            rng.shuffle(&mut shuffled);
+            shuffled.clone()
+        })
+        .collect();
+    let score = |copy: &Vec<&str>| {
+        global_alignment(&seq, copy, &scorer, None, &mut dp::Stats::default())
+            .map(|alignment| alignment.score())
+    };
+
+    let scores = if parallel {
+        let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+        let chunk = copies.len().div_ceil(threads);
+        py.allow_threads(|| {
+            std::thread::scope(|scope| {
+                let workers: Vec<_> = copies
+                    .chunks(chunk)
+                    .map(|chunk| scope.spawn(|| chunk.iter().map(score).collect::<Vec<_>>()))
+                    .collect();
+                workers
+                    .into_iter()
+                    .flat_map(|worker| worker.join().expect("alignments do not panic"))
+                    .collect::<PyResult<Vec<isize>>>()
+            })
+        })?
+    } else {
+        copies.iter().map(score).collect::<PyResult<Vec<isize>>>()?
+    };
+    Ok(mean_and_deviation(&scores))
+}
+
+/// Computes the Levenshtein distance between two sequences using the given edit costs
+#[pyfunction(insert_cost = 1, delete_cost = 1, substitute_cost = 1)]
+fn weighted_edit_distance(
+    a: &PyAny,
+    b: &PyAny,
+    insert_cost: usize,
+    delete_cost: usize,
+    substitute_cost: usize,
+) -> PyResult<usize> {
+    Ok(edit_distance_of_tokens(
+        &tokens("a", a)?,
+        &tokens("b", b)?,
+        insert_cost,
+        delete_cost,
+        substitute_cost,
+    ))
+}
+
+fn edit_distance_of_tokens(
+    a: &[&str],
+    b: &[&str],
+    insert_cost: usize,
+    delete_cost: usize,
+    substitute_cost: usize,
+) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).map(|y| y * insert_cost).collect();
+    for (x, x_token) in a.iter().enumerate() {
+        let mut last_diagonal = row[0];
+        row[0] = (x + 1) * delete_cost;
+        for (y, y_token) in b.iter().enumerate() {
+            let substitute = if x_token == y_token {
+                0
+            } else {
+                substitute_cost
+            };
+            let cost = (last_diagonal + substitute)
+                .min(row[y + 1] + delete_cost)
+                .min(row[y] + insert_cost);
+            last_diagonal = row[y + 1];
+            row[y + 1] = cost;
+        }
+    }
+    row[b.len()]
+}
+
+/// Returns the fewest `(op, position, replacement)` edits turning string `a` into `b`
+///
+/// `op` is `"insert"`, `"delete"` or `"substitute"`, `position` the index of the character
+/// of `a` it applies to, or is inserted before, and `replacement` the character of `b` that
+/// is inserted or substituted in, or `None` for deletions. Positions count characters, not
+/// bytes, and all refer to `a` as it was before any edit.
+#[pyfunction]
+fn diff_ops(a: &str, b: &str) -> Vec<(String, usize, Option<String>)> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let alignment = dp::global(
+        a.len(),
+        b.len(),
+        &dp::Band::full(a.len(), b.len()),
+        dp::Gaps::linear(-1),
+        &mut dp::Stats::default(),
+        |x, y| if a[x] == b[y] { 0 } else { -1 },
+    );
+
+    let mut x_position = 0;
+    let mut ops = vec![];
+    for step in alignment.steps() {
+        match step {
+            Step::Align { x, y } => {
+                if a[x] != b[y] {
+                    ops.push((String::from("substitute"), x, Some(b[y].to_string())));
+                }
+                x_position = x + 1;
+            }
+            Step::Delete { x } => {
+                ops.push((String::from("delete"), x, None));
+                x_position = x + 1;
+            }
+            Step::Insert { y } => {
+                ops.push((String::from("insert"), x_position, Some(b[y].to_string())));
+            }
+        }
+    }
+    ops
+}
+
+/// A Python module implemented in Rust.
+#[pymodule]
+fn sequences(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(align, m)?)?;
+    m.add_function(wrap_pyfunction!(align_with, m)?)?;
+    m.add_function(wrap_pyfunction!(align_iter, m)?)?;
+    m.add_function(wrap_pyfunction!(similarity, m)?)?;
+    m.add_function(wrap_pyfunction!(nearest, m)?)?;
+    m.add_function(wrap_pyfunction!(top_k_matches, m)?)?;
+    m.add_function(wrap_pyfunction!(is_match, m)?)?;
+    m.add_function(wrap_pyfunction!(align_columns_iter, m)?)?;
+    m.add_function(wrap_pyfunction!(seed_and_extend, m)?)?;
+    m.add_function(wrap_pyfunction!(refine, m)?)?;
+    m.add_function(wrap_pyfunction!(align_to_poa, m)?)?;
+    m.add_class::<PartialOrderAlignmentResult>()?;
+    m.add_function(wrap_pyfunction!(windowed_align, m)?)?;
+    m.add_function(wrap_pyfunction!(align_checkpointed, m)?)?;
+    m.add_function(wrap_pyfunction!(local_max, m)?)?;
+    m.add_function(wrap_pyfunction!(local_identity, m)?)?;
+    m.add_function(wrap_pyfunction!(find_all, m)?)?;
+    m.add_function(wrap_pyfunction!(align_to_consensus, m)?)?;
+    m.add_function(wrap_pyfunction!(score_alignment, m)?)?;
+    m.add_function(wrap_pyfunction!(align_weighted, m)?)?;
+    m.add_function(wrap_pyfunction!(find_repeats, m)?)?;
+    m.add_function(wrap_pyfunction!(reverse_complement, m)?)?;
+    m.add_function(wrap_pyfunction!(find_inversions, m)?)?;
+    m.add_function(wrap_pyfunction!(align_objects, m)?)?;
+    m.add_function(wrap_pyfunction!(position_frequencies, m)?)?;
+    m.add_function(wrap_pyfunction!(confusion_matrix, m)?)?;
+    m.add_function(wrap_pyfunction!(merge_via_common, m)?)?;
+    m.add_function(wrap_pyfunction!(weighted_edit_distance, m)?)?;
+    m.add_function(wrap_pyfunction!(diff_ops, m)?)?;
+    m.add_function(wrap_pyfunction!(random_sequence, m)?)?;
+    m.add_function(wrap_pyfunction!(background_score, m)?)?;
+    m.add_class::<AlignmentResult>()?;
+    m.add_class::<AlignmentParams>()?;
+    m.add_class::<Aligner>()?;
+    m.add_class::<Op>()?;
+    m.add_class::<Column>()?;
+    m.add_function(wrap_pyfunction!(load_config, m)?)?;
+    m.add_function(wrap_pyfunction!(named_matrix, m)?)?;
+    m.add_class::<AlignmentDiagnostics>()?;
+    m.add_class::<AlignmentIter>()?;
+    m.add_class::<IncrementalAligner>()?;
+    m.add_class::<AlignmentColumns>()?;
+    m.add_class::<ObjectAlignmentResult>()?;
+    m.add("FORBIDDEN", FORBIDDEN)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs the Python `code`, dedented, with this module as `sequences` and a `raises`
+    /// helper returning the message of the exception a call raises, failing on any exception
+    /// escaping it
+    fn run(code: &str) {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let module = PyModule::new(py, "sequences").unwrap();
+            sequences(py, module).unwrap();
+            let globals = PyDict::new(py);
+            globals.set_item("sequences", module).unwrap();
+            // Imports from Rust called by Python functions look builtins up in these globals.
+            globals
+                .set_item("__builtins__", py.import("builtins").unwrap())
+                .unwrap();
+            let code = py
+                .import("textwrap")
+                .and_then(|textwrap| textwrap.call_method1("dedent", (code,)))
+                .and_then(|code| code.extract::<String>())
+                .unwrap();
+            let result = py
+                .run(HELPERS, Some(globals), None)
+                .and_then(|_| py.run(&code, Some(globals), None));
+            if let Err(error) = result {
+                error.print(py);
+                panic!("{}", error);
+            }
+        });
+    }
+
+    /// Splits ASCII `text` into one token per letter
+    fn letters(text: &str) -> Vec<&str> {
+        (0..text.len()).map(|i| &text[i..i + 1]).collect()
+    }
+
+    const HELPERS: &str = "
+def raises(error, f, *args, **kwargs):
+    try:
+        f(*args, **kwargs)
+    except error as e:
+        return str(e)
+    raise AssertionError(f'{f.__name__} did not raise {error.__name__}')
+";
+
+    #[test]
+    fn authoritative_matrix_mismatches_identical_tokens_it_lacks() {
+        run(r#"
+            matrix = {("x", "y"): 2}
+            assert sequences.align(["a"], ["a"], similarity_matrix=matrix).alignment_score == 1
+            result = sequences.align(
+                ["a"], ["a"], similarity_matrix=matrix, matrix_is_authoritative=True
+            )
+            assert result.alignment_score == -1
+            assert result.alignments == [("a", "a")]
+            matrix = {("x", "y"): 2, ("a", "a"): 3}
+            result = sequences.align(
+                ["a"], ["a"], similarity_matrix=matrix, matrix_is_authoritative=True
+            )
+            assert result.alignment_score == 3
+        "#);
+    }
+
+    #[test]
+    fn edit_script_collapses_runs_of_operations() {
+        run(r#"
+            result = sequences.align(list("abcxde"), list("abyzdef"))
+            assert result.alignments == [
+                ("a", "a"), ("b", "b"), ("c", "y"), ("x", "z"), ("d", "d"), ("e", "e"),
+                ("-", "f"),
+            ], result.alignments
+            assert result.edit_script() == [
+                ("equal", "a b", "a b"),
+                ("replace", "c x", "y z"),
+                ("equal", "d e", "d e"),
+                ("insert", None, "f"),
+            ]
+            assert sequences.align(list("abc"), list("ac")).edit_script() == [
+                ("equal", "a", "a"),
+                ("delete", "b", None),
+                ("equal", "c", "c"),
+            ]
+        "#);
+    }
+
+    #[test]
+    fn weighted_edit_distance_weighs_each_edit_by_its_cost() {
+        let distance = |a: &str, b: &str, insert: usize, delete: usize, substitute: usize| {
+            edit_distance_of_tokens(&letters(a), &letters(b), insert, delete, substitute)
+        };
+        assert_eq!(distance("ab", "axb", 3, 1, 1), 3);
+        assert_eq!(distance("axb", "ab", 1, 3, 1), 3);
+        assert_eq!(distance("ab", "xb", 1, 1, 1), 1);
+        // Substituting costs more than deleting and inserting, so is never done.
+        assert_eq!(distance("ab", "xb", 1, 1, 5), 2);
+        assert_eq!(distance("abc", "abc", 4, 4, 4), 0);
+    }
+
+    #[test]
+    fn forbidden_pair_is_gapped_rather_than_substituted() {
+        run(r#"
+            assert sequences.align(["a"], ["b"]).alignments == [("a", "b")]
+            matrix = {("a", "b"): sequences.FORBIDDEN}
+            result = sequences.align(["a"], ["b"], similarity_matrix=matrix)
+            assert result.alignments == [("-", "b"), ("a", "-")], result.alignments
+            assert result.alignment_score == -2
+            # Forbidding every pair of long sequences does not overflow the fill.
+            result = sequences.align(["a"] * 50, ["b"] * 50, similarity_matrix=matrix)
+            assert result.alignment_score == -100
+            assert result.num_gaps == 100
+        "#);
+    }
+
+    #[test]
+    fn runs_count_consecutive_operations() {
+        run(r#"
+            result = sequences.align(list("abcdef"), list("abxdgef"))
+            assert result.alignments == [
+                ("a", "a"), ("b", "b"), ("c", "x"), ("d", "d"), ("-", "g"), ("e", "e"),
+                ("f", "f"),
+            ], result.alignments
+            assert result.runs == [
+                ("match", 2), ("mismatch", 1), ("match", 1), ("insert", 1), ("match", 2),
+            ]
+            result = sequences.align(list("abc"), list("ac"))
+            assert result.runs == [("match", 1), ("delete", 1), ("match", 1)]
+        "#);
+    }
+
+    #[test]
+    fn align_objects_compares_with_eq_fn_and_fills_gaps() {
+        run(r#"
+            result = sequences.align_objects([1, (2, 3), 4], [1, 4])
+            assert result.alignments == [(1, 1), ((2, 3), "-"), (4, 4)]
+            assert result.alignment_score == 1
+            assert sequences.align_objects([1, 2], [2], gap=None).alignments == [(1, "-"), (2, 2)]
+            assert sequences.align_objects([1, 2], [2], gap=0).alignments == [(1, 0), (2, 2)]
+
+            fold = lambda x, y: x.lower() == y.lower()
+            assert sequences.align_objects(["A", "b"], ["a", "B"]).alignment_score == -2
+            assert sequences.align_objects(["A", "b"], ["a", "B"], eq_fn=fold).alignment_score == 2
+
+            def failing(x, y):
+                raise KeyError(f"cannot compare {x} and {y}")
+            assert "cannot compare A and a" in raises(KeyError, sequences.align_objects, ["A"], ["a"], eq_fn=failing)
+        "#);
+    }
+
+    #[test]
+    fn position_frequencies_counts_tokens_aligned_to_each_reference_position() {
+        run(r#"
+            reference = ["a", "c", "g"]
+            gapped = sequences.align(["a", "g"], reference)
+            substituted = sequences.align(["a", "t", "g"], reference)
+            assert gapped.alignments == [("a", "a"), ("-", "c"), ("g", "g")]
+            assert substituted.alignments == [("a", "a"), ("t", "c"), ("g", "g")]
+            assert sequences.position_frequencies([gapped, substituted], 3) == [
+                {"a": 2}, {"gap": 1, "t": 1}, {"g": 2},
+            ]
+            assert sequences.position_frequencies([gapped], 3, gap_key="-")[1] == {"-": 1}
+            message = raises(ValueError, sequences.position_frequencies, [gapped], 4)
+            assert message == "result aligned to a reference of length 3, expected 4"
+        "#);
+    }
+
+    #[test]
+    fn seed_and_extend_matches_exact_alignment_of_similar_sequences() {
+        run(r#"
+            a = sequences.random_sequence(2000, ["a", "c", "g", "t"], 7)
+            b = list(a)
+            b[300] = "x"
+            del b[900:903]
+            b.insert(1500, "y")
+            b[1800] = "z"
+            exact = sequences.align(a, b)
+            heuristic = sequences.seed_and_extend(a, b)
+            assert heuristic.alignment_score == exact.alignment_score == 1989
+            assert heuristic.cells_evaluated < exact.cells_evaluated // 1000
+        "#);
+    }
+
+    #[test]
+    fn find_repeats_reports_an_internal_repeat() {
+        run(r#"
+            repeats = sequences.find_repeats(list("xyzabcdefgqrstabcdefgmno"), 5)
+            assert len(repeats) >= 1
+            repeat = repeats[0]
+            assert repeat.alignment_score == 7
+            assert (repeat.x_start, repeat.x_end, repeat.y_start, repeat.y_end) == (3, 10, 14, 21)
+            assert repeat.gapped_x() == list("abcdefg")
+            assert raises(ValueError, sequences.find_repeats, ["a"], 0) == "min_score must be positive"
+        "#);
+    }
+
+    #[test]
+    fn wildcard_keys_score_pairs_without_exact_entries() {
+        let matrix = SimilarityMatrix::from([
+            (("ab", "x"), -5),
+            (("a*", "x"), 3),
+            (("abc*", "x"), 4),
+            (("q", "*"), 2),
+        ]);
+        let scorer = Scorer::new(&matrix, 1, -1, dp::Gaps::linear(-1), 1, 1);
+        assert_eq!(scorer.compare("ab", "x"), -5);
+        assert_eq!(scorer.compare("ax", "x"), 3);
+        assert_eq!(scorer.compare("x", "ax"), 3);
+        assert_eq!(scorer.compare("abcd", "x"), 4);
+        assert_eq!(scorer.compare("q", "anything"), 2);
+        assert_eq!(scorer.compare("b", "x"), -1);
+    }
+
+    #[test]
+    fn coverage_of_a_query_fully_aligned_within_a_reference() {
+        run(r#"
+            result = sequences.align(list("bcd"), list("abcdefgh"), end_gap_score=0)
+            assert result.gapped_x() == list("-bcd----")
+            assert result.coverage() == (1.0, 3 / 8)
+        "#);
+    }
+
+    #[test]
+    fn cheaper_end_gaps_select_a_different_alignment() {
+        run(r#"
+            a, b = ["a", "a", "a"], ["b", "a"]
+            result = sequences.align(a, b)
+            assert result.alignments == [("a", "b"), ("a", "a"), ("a", "-")]
+            assert result.alignment_score == -1
+            result = sequences.align(a, b, end_gap_score=0)
+            assert result.alignments == [("-", "b"), ("a", "a"), ("a", "-"), ("a", "-")]
+            assert result.alignment_score == 1
+        "#);
+    }
+
+    #[test]
+    fn secondary_objective_picks_among_co_optimal_alignments() {
+        run(r#"
+            def align(objective):
+                result = sequences.align(["a", "a", "a"], ["a"], secondary_objective=objective)
+                assert result.alignment_score == -1
+                return result.gapped_y()
+
+            assert align("min_gap_runs") == ["a", "-", "-"]
+            assert align("max_gap_runs") == ["-", "a", "-"]
+            assert align("leftmost_gaps") == ["-", "-", "a"]
+            assert align("rightmost_gaps") == ["a", "-", "-"]
+            message = raises(ValueError, align, "fewest_gaps")
+            assert message == 'unknown secondary objective "fewest_gaps"'
+        "#);
+    }
+
+    #[test]
+    fn x_drop_agrees_with_full_local_alignment_on_a_clear_hit() {
+        run(r#"
+            def random(length, seed):
+                return sequences.random_sequence(length, ["a", "c", "g", "t"], seed)
+
+            hit = random(40, 1)
+            a = random(100, 2) + hit + random(100, 3)
+            b = random(60, 4) + hit + random(160, 5)
+            full = sequences.align(a, b, local=True)
+            x_drop = sequences.align(a, b, local=True, x_drop=10)
+            assert x_drop.alignments == full.alignments
+            assert x_drop.alignment_score == full.alignment_score
+            assert x_drop.cells_evaluated < full.cells_evaluated
+            assert raises(ValueError, sequences.align, a, b, x_drop=10) == (
+                "x_drop is only supported for local alignments"
+            )
+        "#);
+    }
+
+    #[test]
+    #[ignore = "benchmark, run with --release --ignored --nocapture"]
+    fn bench_x_drop_local_alignment() {
+        run(r#"
+            import timeit
+            a = sequences.random_sequence(5000, ["a", "c", "g", "t"], 2)
+            b = list(a)
+            full = timeit.timeit(lambda: sequences.align(a, b, local=True), number=3) / 3
+            x_drop = timeit.timeit(
+                lambda: sequences.align(a, b, local=True, x_drop=10), number=3
+            ) / 3
+            print(f"local: {full:.4f}s, x_drop: {x_drop:.4f}s, {full / x_drop:.1f}x faster")
+            assert x_drop < full
+        "#);
+    }
+
+    #[test]
+    fn interned_scores_match_string_scores_on_a_large_vocabulary() {
+        let mut rng = random::SplitMix64::new(0);
+        let vocabulary: Vec<String> = (0..300).map(|i| format!("w{}", i)).collect();
+        let mut sample = |len: usize| -> Vec<&str> {
+            (0..len)
+                .map(|_| vocabulary[rng.below(vocabulary.len())].as_str())
+                .collect()
+        };
+        let (a, b, pairs) = (sample(600), sample(600), sample(1000));
+        let matrix: SimilarityMatrix = pairs
+            .chunks(2)
+            .enumerate()
+            .map(|(i, pair)| ((pair[0], pair[1]), i as isize % 7 - 3))
+            .collect();
+        let scorer = Scorer::new(&matrix, 2, -1, dp::Gaps::linear(-1), a.len(), b.len());
+        let interned = InternedScorer::new(&scorer, &a, &b);
+        assert!(interned.dense.is_none());
+        for (x, x_token) in a.iter().enumerate() {
+            for (y, y_token) in b.iter().enumerate() {
+                assert_eq!(interned.compare(x, y), scorer.compare(x_token, y_token));
+            }
+        }
+
+        let align = |score: &dyn Fn(usize, usize) -> isize| {
+            let start = Instant::now();
+            let band = dp::Band::full(a.len(), b.len());
+            let alignment = dp::global(
+                a.len(),
+                b.len(),
+                &band,
+                scorer.gaps,
+                &mut dp::Stats::default(),
+                score,
+            );
+            let masks: Vec<StepMask> = alignment.steps().map(|step| step.mask()).collect();
+            (alignment.score(), masks, start.elapsed())
+        };
+        let (by_id, id_masks, id_time) = align(&|x, y| interned.compare(x, y));
+        let (by_token, token_masks, token_time) = align(&|x, y| scorer.compare(a[x], b[y]));
+        assert_eq!((by_id, id_masks), (by_token, token_masks));
+        println!("interned: {:?}, by token: {:?}", id_time, token_time);
+    }
+
+    #[test]
+    #[ignore = "benchmark, run with --release --ignored --nocapture"]
+    fn bench_dense_scores_on_dna() {
+        let mut rng = random::SplitMix64::new(1);
+        let nucleotides = ["A", "C", "G", "T"];
+        let mut sample =
+            |len: usize| -> Vec<&str> { (0..len).map(|_| nucleotides[rng.below(4)]).collect() };
+        let (a, b) = (sample(3000), sample(3000));
+        let transitions = [("A", "G"), ("G", "A"), ("C", "T"), ("T", "C")];
+        let matrix: SimilarityMatrix = transitions.iter().map(|&pair| (pair, -1)).collect();
+        let scorer = Scorer::new(&matrix, 2, -3, dp::Gaps::linear(-4), a.len(), b.len());
+        let dense = InternedScorer::new(&scorer, &a, &b);
+        let table = dense.dense.as_ref().expect("DNA is a dense vocabulary");
+        let sparse = InternedScorer {
+            scorer: &scorer,
+            x_seq: &a,
+            y_seq: &b,
+            x_ids: dense.x_ids.clone(),
+            y_ids: dense.y_ids.clone(),
+            scores: (0..table.len() as u32)
+                .cartesian_product(0..table.len() as u32)
+                .map(|(x, y)| ((x, y), table[x as usize][y as usize]))
+                .collect(),
+            dense: None,
+        };
+
+        let align = |interned: &InternedScorer| {
+            let start = Instant::now();
+            let band = dp::Band::full(a.len(), b.len());
+            let alignment = dp::global(
+                a.len(),
+                b.len(),
+                &band,
+                scorer.gaps,
+                &mut dp::Stats::default(),
+                |x, y| interned.compare(x, y),
+            );
+            (alignment.score(), start.elapsed())
+        };
+        let (dense_score, dense_time) = align(&dense);
+        let (sparse_score, sparse_time) = align(&sparse);
+        assert_eq!(dense_score, sparse_score);
+        println!(
+            "dense: {:?}, hashed: {:?}, {:.1}x faster",
+            dense_time,
+            sparse_time,
+            sparse_time.as_secs_f64() / dense_time.as_secs_f64()
+        );
+        assert!(dense_time < sparse_time);
+    }
+
+    #[test]
+    fn merge_via_common_lines_up_three_sequences() {
+        run(r#"
+            ab = sequences.align(list("abd"), list("abcd"))
+            bc = sequences.align(list("abcd"), list("axcde"))
+            assert ab.alignments == [("a", "a"), ("b", "b"), ("-", "c"), ("d", "d")]
+            assert bc.alignments == [("a", "a"), ("b", "x"), ("c", "c"), ("d", "d"), ("-", "e")]
+            assert sequences.merge_via_common(ab, bc) == [
+                ["a", "b", "-", "d", "-"],
+                ["a", "b", "c", "d", "-"],
+                ["a", "x", "c", "d", "e"],
+            ]
+            message = raises(ValueError, sequences.merge_via_common, bc, ab)
+            assert message == "alignments do not share a common sequence"
+        "#);
+    }
+
+    #[test]
+    fn diagnostics_describe_the_work_done_when_enabled() {
+        run(r#"
+            a, b = list("kitten"), list("sitting")
+            assert sequences.align(a, b).diagnostics is None
+            diagnostics = sequences.align(a, b, diagnostics=True).diagnostics
+            assert diagnostics is not None
+            assert diagnostics.cells_filled == 7 * 8
+            assert diagnostics.fill_micros >= 0
+            assert diagnostics.traceback_micros >= 0
+        "#);
+    }
+
+    #[test]
+    fn token_classes_score_digits_against_digits_as_matches() {
+        run(r#"
+            digits = lambda token: "digit" if token.isdigit() else token
+            a, b = list("123"), list("456")
+            assert sequences.align(a, b).alignment_score == -3
+            result = sequences.align(a, b, token_class_fn=digits)
+            assert result.alignment_score == 3
+            assert result.alignments == [("1", "4"), ("2", "5"), ("3", "6")]
+            assert result.similarity_score == -1.0
+            assert sequences.align(a, b, token_class_fn=digits, class_identity=True).similarity_score == 1.0
+        "#);
+    }
+
+    #[test]
+    fn max_gaps_forces_an_alignment_with_fewer_gaps() {
+        run(r#"
+            a, b = list("abcd"), list("bcda")
+            shifted = [("a", "-"), ("b", "b"), ("c", "c"), ("d", "d"), ("-", "a")]
+            assert sequences.align(a, b).alignments == shifted
+            result = sequences.align(a, b, max_gaps=1)
+            assert result.alignment_score == -4
+            assert result.alignments == [("a", "b"), ("b", "c"), ("c", "d"), ("d", "a")]
+            # Limits beyond the length of both sequences are no limit at all.
+            assert sequences.align(a, b, max_gaps=2**40).alignments == shifted
+            assert "at most 1 gaps" in raises(ValueError, sequences.align, list("abc"), ["a"], max_gaps=1)
+        "#);
+    }
+
+    #[test]
+    fn ambiguous_consensus_positions_match_any_of_their_tokens() {
+        run(r#"
+            ambiguity = {"R": ["A", "G"]}
+            result = sequences.align_to_consensus(list("AGTA"), list("RRTA"), ambiguity)
+            assert result.alignment_score == 4
+            assert result.alignments == [("A", "R"), ("G", "R"), ("T", "T"), ("A", "A")]
+            assert result.similarity_score == 1.0
+            assert (result.num_matches, result.num_mismatches) == (4, 0)
+            assert result.runs == [("match", 4)] and result.percent_identity() == 100.0
+            result = sequences.align_to_consensus(list("CGTA"), list("RRTA"), ambiguity)
+            assert result.alignment_score == 2
+            assert (result.num_matches, result.num_mismatches) == (3, 1)
+            assert result.differences() == [(0, "C", "R")]
+        "#);
+    }
+
+    #[test]
+    fn rescoring_with_a_harsher_gap_penalty_lowers_the_score_of_a_gappy_alignment() {
+        run(r#"
+            result = sequences.align(list("abcdef"), list("abef"))
+            assert result.alignment_score == 2
+            harsher = result.rescore(gap_score=-3)
+            assert harsher.alignments == result.alignments
+            assert harsher.alignment_score == -2
+            assert harsher.similarity_score < result.similarity_score
+        "#);
+    }
+
+    #[test]
+    fn matching_runs_skips_runs_shorter_than_the_minimum() {
+        run(r#"
+            result = sequences.align(list("abcdxxef"), list("abcdyef"))
+            assert result.matching_runs(1) == [(0, 0, 4), (6, 5, 2)]
+            assert result.matching_runs(3) == [(0, 0, 4)]
+        "#);
+    }
+
+    #[test]
+    fn local_max_finds_the_end_of_the_best_local_alignment() {
+        run(r#"
+            a, b = list("xxabcdyy"), list("zabcdz")
+            # "abcd" ends after 6 tokens of `a` and 5 of `b`.
+            assert sequences.local_max(a, b) == (4, 6, 5)
+            assert sequences.align(a, b, local=True).alignment_score == 4
+        "#);
+    }
+
+    #[test]
+    fn truncating_alignments_keeps_the_statistics_of_the_full_alignment() {
+        run(r#"
+            a, b = list("abcdefghij"), list("abcxefghij")
+            full = sequences.align(a, b)
+            truncated = sequences.align(a, b, max_columns=4)
+            assert not full.truncated and truncated.truncated
+            assert truncated.alignments == [("a", "a"), ("b", "b"), ("i", "i"), ("j", "j")]
+            for field in ["alignment_score", "similarity_score", "num_matches", "num_mismatches",
+                          "num_gaps", "x_end", "y_end"]:
+                assert getattr(truncated, field) == getattr(full, field), field
+            assert truncated.percent_identity() == full.percent_identity() == 90.0
+            assert truncated.cigar() == full.cigar() == "10M"
+            assert not sequences.align(a, b, max_columns=10).truncated
+        "#);
+    }
+
+    #[test]
+    fn alignment_params_configure_align_with() {
+        run(r#"
+            params = sequences.AlignmentParams()
+            assert (params.match_score, params.gap_score, params.local) == (1, -1, False)
+            params.gap_score = -3
+            params.similarity_matrix = {("a", "b"): 2}
+            a, b = list("abc"), list("bbc")
+            result = sequences.align_with(a, b, params)
+            assert result.alignment_score == 4
+            assert result.alignments == [("a", "b"), ("b", "b"), ("c", "c")]
+            assert result.alignments == sequences.align(a, b, gap_score=-3, similarity_matrix={("a", "b"): 2}).alignments
+            assert sequences.AlignmentParams(local=True).local
+        "#);
+    }
+
+    #[test]
+    fn char_weighted_similarity_weighs_long_tokens_more() {
+        run(r#"
+            a, b = ["hello", "world", "a"], ["hello", "world", "b"]
+            # Half the best score, over 2 of 3 columns or 10 of 11 characters.
+            assert abs(sequences.align(a, b).similarity_score - 1 / 3) < 1e-9
+            assert abs(sequences.align(a, b, char_weighted=True).similarity_score - 5 / 11) < 1e-9
+        "#);
+    }
+
+    #[test]
+    fn forbidding_mismatches_gaps_a_divergent_middle() {
+        run(r#"
+            a, b = list("abcxyzdef"), list("abcuvwdef")
+            assert sequences.align(a, b).num_mismatches == 3
+            result = sequences.align(a, b, forbid_mismatch=True)
+            assert (result.num_matches, result.num_mismatches, result.num_gaps) == (6, 0, 6)
+            assert "".join(result.gapped_x()) == "abc---xyzdef"
+            assert "".join(result.gapped_y()) == "abcuvw---def"
+
+            # Tokens the scorer matches count as matches, identical or not.
+            a, b = ["caf\u00e9", "au", "lait"], ["cafe\u0301", "au", "lait"]
+            result = sequences.align(a, b, forbid_mismatch=True, unicode_normalize=True)
+            assert (result.num_matches, result.num_mismatches, result.num_gaps) == (3, 0, 0)
+            assert result.runs == [("match", 3)] and result.differences() == []
+            assert [op for op, _, _ in result.edit_script()] == ["equal"]
+        "#);
+    }
+
+    #[test]
+    fn align_iter_pulls_pairs_only_as_results_are_consumed() {
+        run(r#"
+            pulled = []
+            def pairs():
+                for i in range(10):
+                    pulled.append(i)
+                    yield list("abc"), list("ab" + "cd"[i % 2])
+            results = sequences.align_iter(pairs())
+            assert pulled == []
+            assert next(results).alignment_score == 3
+            assert next(results).alignment_score == 1
+            assert pulled == [0, 1]
+            assert len(list(results)) == 8
+            assert pulled == list(range(10))
+        "#);
+    }
+
+    #[test]
+    fn gapped_rows_join_into_gapped_strings() {
+        run(r#"
+            result = sequences.align(list("kitten"), list("sitting"))
+            assert "".join(result.gapped_x()) == "kitten-"
+            assert "".join(result.gapped_y()) == "sitting"
+            assert result.gapped_x() == [x for x, _ in result.alignments]
+        "#);
+    }
+
+    #[test]
+    fn max_gap_length_splits_a_long_indel() {
+        run(r#"
+            a, b = list("abcdefgh"), list("abgh")
+            assert "".join(sequences.align(a, b).gapped_y()) == "ab----gh"
+            result = sequences.align(a, b, max_gap_length=2)
+            assert result.alignment_score == -2
+            assert "".join(result.gapped_y()) == "ab--g--h"
+            # Limits beyond the length of both sequences are no limit at all.
+            assert "".join(sequences.align(a, b, max_gap_length=2**40).gapped_y()) == "ab----gh"
+            raises(ValueError, sequences.align, a, b, max_gap_length=0)
+        "#);
+    }
+
+    #[test]
+    fn find_inversions_reports_a_reverse_complemented_segment() {
+        run(r#"
+            left, right = "CCGTAATGCCTTTCCCTAACAGAGTTTTTC", "CGGAATTAGATCAGTTAAATGGCAGAAAAC"
+            segment = "GAACTCGTGTTGTCGAGCGA"
+            inverted = "".join(sequences.reverse_complement(list(segment)))
+            a, b = list(left + segment + right), list(left + inverted + right)
+            assert sequences.find_inversions(a, b) == [(30, 50, 30, 50, 24)]
+            assert sequences.find_inversions(a, a) == []
+        "#);
+    }
+
+    #[test]
+    fn similarity_equals_the_similarity_score_of_align() {
+        run(r#"
+            pairs = [("kitten", "sitting"), ("abcdef", "abef"), ("abc", "xyz"), ("", "abc")]
+            options = [{}, {"gap_score": -2}, {"similarity_matrix": {("a", "x"): 3}}, {"end_gap_score": 0}]
+            for a, b in pairs:
+                for kwargs in options:
+                    expected = sequences.align(list(a), list(b), **kwargs).similarity_score
+                    assert sequences.similarity(list(a), list(b), **kwargs) == expected, (a, b, kwargs)
+            assert "local" in raises(TypeError, sequences.similarity, list("abc"), list("abd"), local=True)
+        "#);
+    }
+
+    #[test]
+    fn score_breakdown_sums_to_the_alignment_score() {
+        run(r#"
+            a, b = list("abcdef"), list("abxef")
+            result = sequences.align(a, b, gap_score=-2)
+            assert result.score_breakdown() == {"match": 4, "mismatch": -1, "gap": -2}
+            for kwargs in [{}, {"similarity_matrix": {("c", "x"): 2}}, {"gap_open_score": -3},
+                           {"end_gap_score": 0}, {"local": True}]:
+                result = sequences.align(list("abcdefgh"), list("zabxgh"), **kwargs)
+                assert sum(result.score_breakdown().values()) == result.alignment_score, kwargs
+        "#);
+    }
+
+    #[test]
+    fn terminal_gaps_score_differently_from_interior_gaps_of_the_same_length() {
+        run(r#"
+            scores = dict(gap_score=-2, end_gap_score=-1, mismatch_score=-10)
+            assert sequences.align(list("axybc"), list("abc"), **scores).alignment_score == -1
+            # Two gaps at any of the four ends cost half as much.
+            for a, b in [("xyabc", "abc"), ("abcxy", "abc"), ("abc", "xyabc"), ("abc", "abcxy")]:
+                assert sequences.align(list(a), list(b), **scores).alignment_score == 1, (a, b)
+            opened = sequences.align(list("abcxy"), list("abc"), end_gap_open_score=-4, **scores)
+            assert opened.alignment_score == 3 - 4 - 1
+        "#);
+    }
+
+    #[test]
+    fn updating_an_aligner_score_changes_its_alignments() {
+        run(r#"
+            aligner = sequences.Aligner()
+            a, b = list("abc"), list("axc")
+            assert aligner.align(a, b).alignments == [("a", "a"), ("b", "x"), ("c", "c")]
+            assert aligner.get_score("b", "x") is None
+            aligner.set_score("b", "x", -10)
+            assert aligner.get_score("x", "b") == -10
+            result = aligner.align(a, b)
+            assert result.alignment_score == 0
+            assert result.alignments == [("a", "a"), ("-", "x"), ("b", "-"), ("c", "c")]
+
+            # Fields of params are set on the aligner's own options, not on a copy.
+            aligner.params.gap_score = -3
+            assert aligner.params.gap_score == -3
+            assert aligner.align(a, b).alignment_score == 2 - 6
+            params = sequences.AlignmentParams()
+            aligner = sequences.Aligner(params)
+            params.mismatch_score = -5
+            assert aligner.align(a, b).alignment_score == 2 - 2
+            aligner.params = sequences.AlignmentParams(match_score=3)
+            assert aligner.align(a, b).alignment_score == 6 - 1
+        "#);
+    }
+
+    #[test]
+    fn random_sequences_are_reproducible_from_their_seed() {
+        run(r#"
+            alphabet = list("acgt")
+            first = sequences.random_sequence(100, alphabet, 42)
+            assert len(first) == 100 and set(first) <= set(alphabet)
+            assert sequences.random_sequence(100, alphabet, 42) == first
+            assert sequences.random_sequence(100, alphabet, 43) != first
+        "#);
+    }
+
+    #[test]
+    fn wildcard_row_scores_a_token_against_everything_unlisted() {
+        run(r#"
+            matrix = {("n", "*"): 2, ("n", "a"): -3}
+            score = lambda x, y: sequences.align([x], [y], gap_score=-5, similarity_matrix=matrix).alignment_score
+            for y in "bcn":
+                assert score("n", y) == score(y, "n") == 2, y
+            assert score("n", "a") == score("a", "n") == -3
+            assert score("b", "c") == -1
+        "#);
+    }
+
+    #[test]
+    fn soft_clips_and_query_consuming_operations_cover_the_query() {
+        run(r#"
+            import re
+            for a, b, cigar in [("xxxabcdefgyy", "qqabcXdefgqq", "3S3M1D4M2S"),
+                                ("xxxabcdXefgyy", "abcdefg", "3S4M1I3M2S")]:
+                result = sequences.align(list(a), list(b), local=True)
+                assert result.cigar() == cigar
+                assert result.clips() == (3, 2)
+                consumed = sum(int(n) for n, op in re.findall(r"(\d+)([MIDS])", cigar) if op in "MIS")
+                assert consumed == len(a)
+        "#);
+    }
+
+    #[test]
+    fn a_strong_alignment_is_more_significant_than_a_random_one() {
+        run(r#"
+            a = sequences.random_sequence(60, list("acgt"), 1)
+            unrelated = sequences.random_sequence(60, list("acgt"), 2)
+            assert sequences.align(a, a).z_score is None
+            strong = sequences.align(a, a, estimate_significance=True)
+            random = sequences.align(a, unrelated, estimate_significance=True)
+            assert strong.z_score > 10 > random.z_score
+            assert strong.p_value < random.p_value
+            again = sequences.align(a, unrelated, estimate_significance=True)
+            assert (again.z_score, again.p_value) == (random.z_score, random.p_value)
+            raises(ValueError, sequences.align, a, a, estimate_significance=True, significance_shuffles=0)
+        "#);
+    }
+
+    #[test]
+    fn results_sort_by_score() {
+        run(r#"
+            query = list("abcdef")
+            targets = ["abcxef", "abcdef", "xyzxyz", "abdf"]
+            results = [sequences.align(query, list(target)) for target in targets]
+            ranked = sorted(results, reverse=True)
+            assert [r.alignment_score for r in ranked] == [6, 4, 2, -6]
+            assert ranked[0] > ranked[1] >= ranked[2]
+            twin = sequences.align(query, list("abcdef"))
+            assert twin == results[1] and hash(twin) == hash(results[1])
+            assert len({*results, twin}) == 4
+        "#);
+    }
+
+    #[test]
+    fn collapsing_gaps_merges_an_insertion_into_one_column() {
+        run(r#"
+            a, b = list("abcd"), list("abxyzcd")
+            full = sequences.align(a, b)
+            collapsed = sequences.align(a, b, collapse_gaps=True)
+            assert [x for x, _ in full.alignments].count("-") == 3
+            assert collapsed.alignments == [("a", "a"), ("b", "b"), ("---", "x y z"), ("c", "c"), ("d", "d")]
+            assert (collapsed.num_gaps, collapsed.x_end, collapsed.y_end) == (full.num_gaps, 4, 7) == (3, 4, 7)
+        "#);
+    }
+
+    #[test]
+    fn score_alignment_scores_a_hand_built_alignment() {
+        run(r#"
+            result = sequences.score_alignment(list("ab-cd"), list("abxc-"))
+            assert result.alignments == [("a", "a"), ("b", "b"), ("-", "x"), ("c", "c"), ("d", "-")]
+            assert result.alignment_score == 3 - 2
+            assert (result.num_matches, result.num_mismatches, result.num_gaps) == (3, 0, 2)
+            assert result.percent_identity() == 60.0
+            assert abs(result.similarity_score - 1 / 3 * 3 / 5) < 1e-9
+            assert sequences.score_alignment(list("ab-cd"), list("abxc-"), end_gap_score=0).alignment_score == 2
+            assert "differ in length" in raises(ValueError, sequences.score_alignment, list("ab"), ["a"])
+            assert "gap in both" in raises(ValueError, sequences.score_alignment, list("a-"), list("a-"))
+        "#);
+    }
+
+    #[test]
+    fn weighted_matches_outscore_equally_many_light_ones() {
+        run(r#"
+            # Either "the" or "rare" can be aligned, but not both.
+            a, b = [("the", 1.0), ("rare", 3.0)], [("rare", 3.0), ("the", 1.0)]
+            result = sequences.align_weighted(a, b)
+            assert result.alignments == [("the", "-"), ("rare", "rare"), ("-", "the")]
+            assert result.alignment_score == 9 - 2
+            a, b = [("the", 3.0), ("rare", 1.0)], [("rare", 1.0), ("the", 3.0)]
+            assert sequences.align_weighted(a, b).alignments == [("-", "rare"), ("the", "the"), ("rare", "-")]
+            assert "invalid weight" in raises(ValueError, sequences.align_weighted, [("a", -1.0)], [("a", 1.0)])
+        "#);
+    }
+
+    #[test]
+    fn delete_and_insert_symbols_mark_their_own_rows() {
+        run(r#"
+            result = sequences.align(list("abxcd"), list("abcyd"), delete_symbol="<", insert_symbol=">")
+            assert result.alignments == [("a", "a"), ("b", "b"), ("x", "<"), ("c", "c"), (">", "y"), ("d", "d")]
+            xs, ys = zip(*result.alignments)
+            assert ">" in xs and "<" not in xs and "-" not in xs
+            assert "<" in ys and ">" not in ys and "-" not in ys
+            plain = sequences.align(list("abxcd"), list("abcyd"), gap_symbol="_")
+            assert ("x", "_") in plain.alignments and ("_", "y") in plain.alignments
+        "#);
+    }
+
+    #[test]
+    fn pushing_tokens_one_at_a_time_matches_aligning_from_scratch() {
+        run(r#"
+            a = list("gattaca")
+            incremental = sequences.IncrementalAligner(a, 2, -1, -2)
+            b = []
+            for token in "gcatgcu":
+                b.append(token)
+                score = incremental.push_b(token)
+                expected = sequences.align(a, b, match_score=2, mismatch_score=-1, gap_score=-2)
+                assert score == expected.alignment_score, b
+                assert incremental.alignment().alignments == expected.alignments, b
+        "#);
+    }
+
+    #[test]
+    fn exported_matrix_holds_blosum62_entries_and_mirrors() {
+        run(r#"
+            blosum62 = sequences.named_matrix("BLOSUM62")
+            assert len(blosum62) == 24 * 24
+            assert all(blosum62[y, x] == score for (x, y), score in blosum62.items())
+            assert sequences.named_matrix("blosum62") == blosum62
+            assert "unknown similarity matrix" in raises(ValueError, sequences.named_matrix, "PAM250")
+
+            aligner = sequences.Aligner(sequences.AlignmentParams(similarity_matrix=blosum62))
+            aligner.set_score("J", "A", 3)
+            exported = aligner.matrix()
+            assert (exported["W", "W"], exported["A", "R"], exported["R", "A"], exported["E", "Q"]) == (11, -1, -1, 2)
+            assert exported["A", "J"] == exported["J", "A"] == 3
+            assert aligner.align(list("WCA"), list("WCR")).alignment_score == 11 + 9 - 1
+        "#);
+    }
+
+    #[test]
+    fn nearest_ranks_a_small_corpus_by_similarity() {
+        run(r#"
+            corpus = [list(word) for word in ["sitting", "kitten", "mitten", "dog", "kitchen"]]
+            nearest = sequences.nearest(list("kitten"), corpus, 3)
+            assert [index for index, _ in nearest] == [1, 2, 4]
+            assert nearest[0][1] == 1.0
+            assert [index for index, _ in sequences.nearest(list("kitten"), corpus, 10)] == [1, 2, 4, 0, 3]
+            assert sequences.nearest(list("kitten"), corpus, 0) == []
+        "#);
+    }
+
+    #[test]
+    fn malformed_sequences_raise_errors_naming_the_argument() {
+        run(r#"
+            ok = list("acgt")
+            assert "a must be a list of str tokens, not a str" in raises(TypeError, sequences.align, "acgt", ok)
+            assert raises(TypeError, sequences.align, ok, 5) == "b must be a list of str tokens, got int"
+            assert raises(TypeError, sequences.align, ok, ["a", 1]) == "b[1] must be a str token, got int"
+            assert raises(TypeError, sequences.top_k_matches, ok, ok, 1).startswith("refs[0] must be a list of str tokens, not a str")
+            assert raises(TypeError, sequences.align_weighted, [("a", 1.0)], [("a", "x")]) == "b[0] must be a (str, float) pair, got tuple"
+
+            calls = {
+                "local_max": lambda a, b: sequences.local_max(a, b),
+                "local_identity": lambda a, b: sequences.local_identity(a, b),
+                "is_match": lambda a, b: sequences.is_match(a, b, 0.5),
+                "find_all": lambda a, b: sequences.find_all(a, b, 1),
+                "align_to_consensus": lambda a, b: sequences.align_to_consensus(a, b, {}),
+                "score_alignment": lambda a, b: sequences.score_alignment(a, b),
+                "find_inversions": lambda a, b: sequences.find_inversions(a, b),
+                "align_columns_iter": lambda a, b: sequences.align_columns_iter(a, b),
+                "windowed_align": lambda a, b: sequences.windowed_align(a, b, 4, 1),
+                "align_checkpointed": lambda a, b: sequences.align_checkpointed(a, b, 2),
+                "seed_and_extend": lambda a, b: sequences.seed_and_extend(a, b),
+                "weighted_edit_distance": lambda a, b: sequences.weighted_edit_distance(a, b),
+            }
+            for name, call in calls.items():
+                message = raises(TypeError, call, ok, [None])
+                assert message.endswith("[0] must be a str token, got NoneType"), (name, message)
+                message = raises(TypeError, call, "acgt", ok)
+                assert "must be a list of str tokens, not a str" in message, (name, message)
+            prior = sequences.align(ok, ok)
+            assert raises(TypeError, sequences.refine, ok, "acgt", prior, 1).startswith("b must be")
+            for call, args in [(sequences.find_repeats, [1]), (sequences.reverse_complement, []),
+                               (sequences.background_score, [4]), (sequences.align_to_poa, [[], []])]:
+                assert raises(TypeError, call, "acgt", *args).startswith("seq must be")
+            assert raises(TypeError, sequences.random_sequence, 3, "acgt", 0).startswith("alphabet must be")
+        "#);
+    }
+
+    #[test]
+    fn matrix_as_floor_keeps_the_better_of_the_matrix_and_the_defaults() {
+        run(r#"
+            matrix = {("a", "a"): -2, ("a", "b"): 3}
+            a, b = ["a", "a"], ["a", "b"]
+            overridden = sequences.align(a, b, similarity_matrix=matrix, gap_score=-10)
+            assert overridden.alignment_score == -2 + 3
+            floored = sequences.align(a, b, similarity_matrix=matrix, gap_score=-10, matrix_as_floor=True)
+            # The identical pair scores its default match instead, the other pair its entry.
+            assert floored.alignment_score == 1 + 3
+        "#);
+    }
+
+    #[test]
+    fn gap_compressed_identity_counts_a_long_indel_once() {
+        run(r#"
+            result = sequences.align(list("abcdefghij"), list("abcdghij"))
+            assert (result.num_matches, result.num_mismatches, result.num_gaps) == (8, 0, 2)
+            assert result.percent_identity() == 80.0
+            assert result.gap_compressed_identity == 8 / 9
+            assert sequences.align(list("abcdefghij"), list("abcdefghij")).gap_compressed_identity == 1.0
+        "#);
+    }
+
+    #[test]
+    fn banded_alignment_evaluates_fewer_cells_than_full_alignment() {
+        run(r#"
+            a = sequences.random_sequence(300, list("acgt"), 5)
+            b = a[:100] + a[101:200] + ["g"] + a[200:]
+            full = sequences.align(a, b)
+            assert full.cells_evaluated == 301 * 301
+            banded = sequences.refine(a, b, full, 2)
+            assert banded.alignment_score == full.alignment_score
+            assert banded.cells_evaluated < full.cells_evaluated / 10
+            seeded = sequences.seed_and_extend(a, b)
+            assert seeded.cells_evaluated < banded.cells_evaluated
+        "#);
+    }
+
+    #[test]
+    fn affine_gaps_select_gotoh_and_linear_gaps_needleman_wunsch() {
+        run(r#"
+            a, b = list("abcdefgh"), list("abgh")
+            affine = sequences.align(a, b, gap_open_score=-3)
+            assert affine.algorithm == "gotoh"
+            # One run of four gaps: opened once, extended three times.
+            assert affine.alignment_score == 4 - 3 - 3
+            assert "".join(affine.gapped_y()) == "ab----gh"
+            linear = sequences.align(a, b)
+            assert linear.algorithm == "needleman_wunsch"
+            assert linear.alignment_score == 4 - 4
+            same = sequences.align(a, b, gap_open_score=-1)
+            assert same.algorithm == "needleman_wunsch"
+            assert same.alignments == linear.alignments
+        "#);
+    }
+
+    #[test]
+    fn shared_prefix_alignment_equals_full_alignment() {
+        run(r#"
+            a = sequences.random_sequence(40, list("acgt"), 1)
+            prefix = sequences.random_sequence(30, list("acgt"), 2)
+            aligner = sequences.Aligner()
+            for seed in range(3, 8):
+                b = prefix + sequences.random_sequence(10, list("acgt"), seed)
+                shared = aligner.align_with_shared_prefix(30, a, b)
+                full = sequences.align(a, b)
+                assert shared.alignments == full.alignments, seed
+                assert shared.alignment_score == full.alignment_score, seed
+            assert "exceeds the length" in raises(ValueError, aligner.align_with_shared_prefix, 41, a, prefix)
+        "#);
+    }
+
+    #[test]
+    fn filtering_columns_drops_gaps_and_breaks_contiguity() {
+        run(r#"
+            a, b = list("abxcdef"), list("abcyef")
+            result = sequences.align(a, b)
+            assert any("-" in column for column in result.alignments)
+            filtered = result.filter_columns(-5, drop_gaps=True)
+            assert all("-" not in column for column in filtered.alignments)
+            assert filtered.num_gaps == 0 and filtered.num_matches == result.num_matches
+            assert filtered.alignment_score == result.alignment_score + result.num_gaps
+            assert result.contiguous and result.filter_columns(-5).contiguous
+            assert not filtered.contiguous
+
+            params = sequences.AlignmentParams()
+            for call in [filtered.cigar, lambda: filtered.matching_runs(1), filtered.rescore,
+                         lambda: filtered.multi_score([params]), lambda: sequences.refine(a, b, filtered, 2),
+                         lambda: sequences.merge_via_common(filtered, sequences.align(b, list("abc")))]:
+                assert "needs contiguous columns" in raises(ValueError, call)
+        "#);
+    }
+
+    #[test]
+    fn columnar_dict_has_one_entry_per_column_in_every_list() {
+        run(r#"
+            result = sequences.align(list("abxcd"), list("abcyd"))
+            columns = result.to_columns()
+            assert sorted(columns) == ["op", "x", "x_index", "y", "y_index"]
+            assert {len(values) for values in columns.values()} == {len(result.alignments)} == {6}
+            assert columns["op"] == ["equal", "equal", "delete", "equal", "insert", "equal"]
+            assert columns["x_index"] == [0, 1, 2, 3, None, 4]
+            assert list(zip(columns["x"], columns["y"])) == result.alignments
+        "#);
+    }
+
+    #[test]
+    fn find_all_returns_exact_and_edited_placements() {
+        run(r#"
+            text = list("xxGATTACAyyyyGATTCCAzz")
+            placements = sequences.find_all(list("GATTACA"), text, 4)
+            assert [(p.y_start, p.y_end, p.alignment_score) for p in placements] == [(2, 9, 7), (13, 20, 5)]
+            assert "".join(placements[1].gapped_y()) == "GATTCCA"
+            assert len(sequences.find_all(list("GATTACA"), text, 6)) == 1
+        "#);
+    }
+
+    #[test]
+    fn multi_score_falls_as_the_gap_penalty_rises() {
+        run(r#"
+            result = sequences.align(list("abcdefgh"), list("abefgxh"))
+            assert result.num_gaps == 3
+            param_sets = [sequences.AlignmentParams(gap_score=gap) for gap in [0, -1, -2, -3]]
+            scores = result.multi_score(param_sets)
+            assert scores[1] == result.alignment_score
+            assert scores == sorted(scores, reverse=True) and len(set(scores)) == 4
+            assert [a - b for a, b in zip(scores, scores[1:])] == [3, 3, 3]
+        "#);
+    }
+
+    #[test]
+    fn constructed_results_read_back_their_fields() {
+        run(r#"
+            columns = [("a", "a"), ("b", "-"), ("c", "x")]
+            result = sequences.AlignmentResult(columns, -1, 0.25)
+            assert (result.alignments, result.alignment_score, result.similarity_score) == (columns, -1, 0.25)
+            assert (result.x_start, result.x_end, result.y_start, result.y_end) == (0, 3, 0, 2)
+            assert (result.num_matches, result.num_mismatches, result.num_gaps) == (1, 1, 1)
+            assert result.cigar() == "1M1I1M"
+            assert (result.min_possible_score(), result.max_possible_score()) == (-5, 1)
+            scored = sequences.AlignmentResult(columns, 3, 0.25, match_score=4, mismatch_score=-2, gap_score=-3)
+            assert (scored.min_possible_score(), scored.max_possible_score()) == (-15, 2 * 4 - 3)
+            assert "gap in both" in raises(ValueError, sequences.AlignmentResult, [("-", "-")], 0, 0.0)
+        "#);
+    }
+
+    #[test]
+    fn windowed_alignment_scores_close_to_exact_alignment() {
+        run(r#"
+            import random
+            for seed in range(3):
+                a = sequences.random_sequence(2000, list("acgt"), seed)
+                b, rng = list(a), random.Random(seed)
+                for _ in range(60):
+                    i, edit = rng.randrange(len(b)), rng.randrange(3)
+                    if edit == 0:
+                        b[i] = rng.choice("acgt")
+                    elif edit == 1:
+                        del b[i]
+                    else:
+                        b.insert(i, rng.choice("acgt"))
+                exact = sequences.align(a, b).alignment_score
+                for window, overlap in [(200, 50), (64, 16)]:
+                    windowed = sequences.windowed_align(a, b, window, overlap)
+                    assert (windowed.x_end, windowed.y_end) == (len(a), len(b))
+                    assert exact - windowed.alignment_score <= exact / 100, (seed, window)
+            assert "must be less than window" in raises(ValueError, sequences.windowed_align, a, b, 16, 16)
+        "#);
+    }
+
+    #[test]
+    fn diff_ops_matches_a_hand_computed_edit_script() {
+        run(r#"
+            assert sequences.diff_ops("kitten", "sitting") == [
+                ("substitute", 0, "s"), ("substitute", 4, "i"), ("insert", 6, "g")
+            ]
+            assert sequences.diff_ops("abc", "abc") == []
+            assert sequences.diff_ops("", "ab") == [("insert", 0, "a"), ("insert", 0, "b")]
+            assert sequences.diff_ops("ab", "") == [("delete", 0, None), ("delete", 1, None)]
+        "#);
+    }
+
+    #[test]
+    fn is_match_compares_each_metric_with_the_threshold() {
+        run(r#"
+            a, b = list("abcdefghij"), list("abcxefghij")
+            assert abs(sequences.align(a, b).similarity_score - 0.8) < 1e-9
+            for metric, score in [("similarity", 0.8 - 1e-9), ("percent_identity", 90.0), ("normalized", 0.8)]:
+                assert sequences.is_match(a, b, score, metric=metric), metric
+                assert not sequences.is_match(a, b, score + 0.01, metric=metric), metric
+            assert sequences.is_match(a, b, 0.79)
+            assert "metric" in raises(ValueError, sequences.is_match, a, b, 0.5, metric="jaccard")
+        "#);
+    }
+
+    #[test]
+    fn alignment_scores_lie_within_the_possible_scores() {
+        run(r#"
+            def within(result):
+                return result.min_possible_score() <= result.alignment_score <= result.max_possible_score()
+
+            pairs = [("abcdef", "abef"), ("kitten", "sitting"), ("xxabcdyy", "zabcdz"), ("aaaa", "a"),
+                     ("", "abc"), ("abc", "xyz")]
+            options = [{}, {"local": True}, {"gap_score": -3, "gap_open_score": -5}, {"end_gap_score": 0},
+                       {"similarity_matrix": {("a", "z"): 5}}, {"match_score": 3, "gap_score": 2}]
+            for a, b in pairs:
+                for kwargs in options:
+                    result = sequences.align(list(a), list(b), **kwargs)
+                    assert within(result), (a, b, kwargs)
+                    assert within(result.rescore(gap_score=-4)), (a, b, kwargs)
+                    assert within(result.rescore(match_score=5, mismatch_score=-3)), (a, b, kwargs)
+            assert sequences.align(list("abc"), list("abc")).rescore(match_score=5).max_possible_score() == 15
+
+            weighted = sequences.align_weighted([("a", 3.0), ("b", 4.0)], [("a", 2.0), ("b", 4.0)])
+            assert weighted.alignment_score == 6 + 16 and weighted.max_possible_score() == 2 * 16
+            for placement in sequences.find_all(list("GATTACA"), list("xxGATTACAyyyyGATTCCAzz"), 4):
+                assert within(placement)
+        "#);
+    }
+
+    #[test]
+    fn idf_weighted_scores_lie_within_the_possible_scores() {
+        run(r#"
+            idf = {"rare": 5.0, "the": 0.2}
+            result = sequences.align(["the", "rare", "cat"], ["rare", "the", "cat"], idf=idf,
+                                     match_score=10, mismatch_score=-10, gap_score=-1)
+            assert ("rare", "rare") in result.alignments
+            assert result.alignment_score > 10 * 3
+            assert result.min_possible_score() <= result.alignment_score <= result.max_possible_score()
+            assert result.rescore(gap_score=-4).alignment_score <= result.max_possible_score()
+        "#);
+    }
+
+    #[test]
+    fn checkpointed_alignment_equals_full_alignment() {
+        run(r#"
+            import random
+            rng = random.Random(3)
+            a = [rng.choice("ACGT") for _ in range(300)]
+            b = list(a)
+            for i in (40, 120, 200):
+                b[i] = "C" if b[i] == "A" else "A"
+            del b[150:153]
+            b[60:60] = ["G", "G"]
+
+            full = sequences.align(a, b)
+            for checkpoint_stride in (1, 7, 17, 500):
+                result = sequences.align_checkpointed(a, b, checkpoint_stride)
+                assert result.alignments == full.alignments
+                assert result.alignment_score == full.alignment_score
+            assert "positive" in raises(ValueError, sequences.align_checkpointed, a, b, 0)
+        "#);
+    }
+
+    #[test]
+    fn confusion_matrix_counts_substitutions_across_results() {
+        run(r#"
+            results = [sequences.align(list("kitten"), list("sitten")),
+                       sequences.align(list("kite"), list("site")),
+                       sequences.align(list("mitten"), list("sittin"))]
+            assert sequences.confusion_matrix(results) == {("k", "s"): 2, ("m", "s"): 1, ("e", "i"): 1}
+            assert sequences.confusion_matrix([]) == {}
+        "#);
+    }
+
+    #[test]
+    fn refining_a_near_optimal_prior_recovers_the_optimum() {
+        run(r#"
+            a, b = list("GATTACAGATTACA"), list("GATTACGATTACA")
+            prior = sequences.score_alignment(a, list("GATTACGAT-TACA"))
+            full = sequences.align(a, b)
+            assert prior.alignment_score < full.alignment_score
+
+            refined = sequences.refine(a, b, prior, 2)
+            assert refined.alignments == full.alignments
+            assert refined.alignment_score == full.alignment_score
+            assert refined.cells_evaluated < full.cells_evaluated
+            assert sequences.refine(a, b, prior, 0).alignment_score == prior.alignment_score
+        "#);
+    }
+
+    #[test]
+    fn background_scores_are_reproducible_from_their_seed() {
+        run(r#"
+            seq = list("ABCABCAB")
+            mean, std = sequences.background_score(seq, 20, seed=5)
+            assert sequences.background_score(seq, 20, seed=5) == (mean, std)
+            assert sequences.background_score(seq, 20, seed=5, parallel=True) == (mean, std)
+            assert sequences.background_score(seq, 20, seed=6) != (mean, std)
+            assert std > 0 and mean < sequences.align(seq, seq).alignment_score
+        "#);
+    }
+
+    #[test]
+    fn unicode_normalization_matches_precomposed_and_decomposed_accents() {
+        run(r#"
+            precomposed, decomposed = ["caf\u00e9", "au", "lait"], ["cafe\u0301", "au", "lait"]
+            assert sequences.align(precomposed, decomposed).alignment_score == 1
+            for kwargs in [{}, {"char_weighted": True}, {"idf_similarity": True, "idf": {"au": 0.1}}]:
+                result = sequences.align(precomposed, decomposed, unicode_normalize=True, **kwargs)
+                assert result.alignment_score == sequences.align(precomposed, precomposed, **kwargs).alignment_score
+                assert result.similarity_score == 1.0, kwargs
+                assert result.alignments[0] == ("caf\u00e9", "cafe\u0301")
+                assert (result.num_matches, result.num_mismatches) == (3, 0)
+                assert result.differences() == [] and result.percent_identity() == 100.0
+                assert result.runs == [("match", 3)]
+                assert sequences.confusion_matrix([result]) == {}
+            unnormalized = sequences.align(precomposed, decomposed)
+            assert unnormalized.differences() == [(0, "caf\u00e9", "cafe\u0301")]
+        "#);
+    }
+
+    #[test]
+    fn loaded_configs_reproduce_align_and_reject_malformed_fields() {
+        run(r#"
+            import json, os, tempfile
+            path = os.path.join(tempfile.mkdtemp(), "config.json")
+            def load(config):
+                with open(path, "w") as file:
+                    file.write(config if isinstance(config, str) else json.dumps(config))
+                return sequences.load_config(path)
+
+            config = {"match_score": 2, "mismatch_score": -1, "gap_score": -2,
+                      "similarity_matrix": [["c", "e", 1]], "mode": "local"}
+            a, b = list("xxabcyy"), list("zabez")
+            expected = sequences.align(a, b, local=True, match_score=2, mismatch_score=-1, gap_score=-2,
+                                       similarity_matrix={("c", "e"): 1})
+            result = load(config).align(a, b)
+            assert result.alignments == expected.alignments == [("a", "a"), ("b", "b"), ("c", "e")]
+            assert result.alignment_score == expected.alignment_score == 5
+
+            required = {"match_score": 1, "mismatch_score": -1, "gap_score": -1}
+            assert "JSON object" in raises(ValueError, load, "[1]")
+            assert "not valid JSON" in raises(ValueError, load, "{")
+            assert "missing \"match_score\"" in raises(ValueError, load, {"mismatch_score": -1, "gap_score": -1})
+            assert "must be an integer" in raises(ValueError, load, {**required, "gap_score": True})
+            assert "unknown config field" in raises(ValueError, load, {**required, "colour": 1})
+            assert "\"global\" or \"local\"" in raises(ValueError, load, {**required, "mode": "semi"})
+            assert "triples" in raises(ValueError, load, {**required, "similarity_matrix": [["a", "b"]]})
+        "#);
+    }
+
+    #[test]
+    fn ops_iterate_columns_with_their_tokens() {
+        let (a, b) = (letters("abcd"), letters("axdy"));
+        let steps = [
+            Step::Align { x: 0, y: 0 },
+            Step::Align { x: 1, y: 1 },
+            Step::Delete { x: 2 },
+            Step::Align { x: 3, y: 2 },
+            Step::Insert { y: 3 },
+        ];
+        assert_eq!(
+            ops(&a, &b, steps).collect::<Vec<_>>(),
+            [
+                (Op::Align, Some("a"), Some("a")),
+                (Op::Align, Some("b"), Some("x")),
+                (Op::Delete, Some("c"), None),
+                (Op::Align, Some("d"), Some("d")),
+                (Op::Insert, None, Some("y")),
+            ]
+        );
+    }
+
+    #[test]
+    fn columns_label_each_column_with_its_op() {
+        run(r#"
+            columns = sequences.align(list("abcde"), list("axdey")).columns()
+            assert [column.op for column in columns] == [
+                sequences.Op.Align, sequences.Op.Align, sequences.Op.Delete, sequences.Op.Align,
+                sequences.Op.Align, sequences.Op.Insert,
+            ]
+            assert [(column.a, column.b) for column in columns] == [
+                ("a", "a"), ("b", "x"), ("c", None), ("d", "d"), ("e", "e"), (None, "y"),
+            ]
+            assert repr(columns[2]) == 'Column(op=Op.Delete, a="c", b=None)'
+        "#);
+    }
+
+    #[test]
+    fn correctness_rules_differ_on_an_identical_pair_scoring_negatively() {
+        run(r#"
+            a, b = list("abXc"), list("abXd")
+            matrix = {("X", "X"): -1, ("c", "d"): 3}
+            def similarity(rule):
+                result = sequences.align(a, b, similarity_matrix=matrix, gap_score=-3, correctness_rule=rule)
+                assert result.alignments == [("a", "a"), ("b", "b"), ("X", "X"), ("c", "d")]
+                assert result.alignment_score == 4
+                return result.similarity_score
+
+            # Correct pairs are a, b and X; a, b and (c, d); and only a and b.
+            assert abs(similarity("identity") - 4 / 1 * 3 / 4) < 1e-9
+            assert abs(similarity("positive_score") - 4 / 5 * 3 / 4) < 1e-9
+            assert abs(similarity("identity_and_positive") - 4 / 2 * 2 / 4) < 1e-9
+            assert similarity("identity") == sequences.align(a, b, similarity_matrix=matrix, gap_score=-3).similarity_score
+            assert "unknown correctness rule" in raises(ValueError, sequences.align, a, b, correctness_rule="exact")
+        "#);
+    }
+
+    #[test]
+    fn differences_return_only_the_disagreeing_columns() {
+        run(r#"
+            result = sequences.align(list("kitten"), list("sitting"))
+            assert result.differences() == [(0, "k", "s"), (4, "e", "i"), (6, "-", "g")]
+            assert sequences.align(list("abc"), list("abc")).differences() == []
+        "#);
+    }
+
+    #[test]
+    fn projected_labels_follow_a_substitution_and_skip_an_insertion() {
+        run(r#"
+            result = sequences.align(list("abcd"), list("axcyd"))
+            assert result.alignments == [("a", "a"), ("b", "x"), ("c", "c"), ("-", "y"), ("d", "d")]
+            assert result.project_labels(["A", "B", None, "D"]) == ["A", "B", None, None, "D"]
+            assert "expected 4 labels" in raises(ValueError, result.project_labels, ["A"])
+        "#);
+    }
+
+    #[test]
+    fn partial_order_alignment_follows_the_matching_branch_of_a_bubble() {
+        run(r#"
+            # G A (T | C) T A
+            nodes = [(0, "G"), (1, "A"), (2, "T"), (3, "C"), (4, "T"), (5, "A")]
+            edges = [(0, 1), (1, 2), (1, 3), (2, 4), (3, 4), (4, 5)]
+            result = sequences.align_to_poa(list("GACTA"), nodes, edges)
+            assert (result.path, result.alignment_score) == ([0, 1, 3, 4, 5], 5)
+            assert result.alignments == [("G", "G"), ("A", "A"), ("C", "C"), ("T", "T"), ("A", "A")]
+            assert sequences.align_to_poa(list("GATTA"), nodes, edges).path == [0, 1, 2, 4, 5]
+
+            result = sequences.align_to_poa(list("GACA"), nodes, edges)
+            assert (result.path, result.alignment_score) == ([0, 1, 3, 4, 5], 3)
+            assert "cycle" in raises(ValueError, sequences.align_to_poa, ["G"], nodes, edges + [(5, 0)])
+        "#);
+    }
+
+    #[test]
+    fn collapsing_runs_aligns_homopolymer_length_errors_as_matches() {
+        run(r#"
+            a, b = list("GAAAATTC"), list("GAATTTTC")
+            assert ("A", "T") in sequences.align(a, b).alignments
+
+            collapsed = sequences.align(a, b, collapse_runs=True)
+            assert collapsed.alignment_score == sequences.align(list("GATC"), list("GATC")).alignment_score
+            assert collapsed.num_mismatches == 0
+            assert collapsed.alignments == [("G", "G"), ("A", "A"), ("A", "A"), ("A", "-"), ("A", "-"),
+                                            ("T", "T"), ("T", "T"), ("-", "T"), ("-", "T"), ("C", "C")]
+
+            # The expanded columns are scored on the token classes, as the collapsed ones were.
+            result = sequences.align(list("GAAAc"), list("gAC"), collapse_runs=True, token_class_fn=str.lower,
+                                     gap_score=-2)
+            assert result.alignments == [("G", "g"), ("A", "A"), ("A", "-"), ("A", "-"), ("c", "C")]
+            assert (result.num_matches, result.num_mismatches, result.alignment_score) == (1, 2, 3)
+            assert result.score_breakdown() == {"match": 1, "mismatch": 2, "gap": -4}
+        "#);
+    }
+
+    #[test]
+    fn one_based_positions_are_one_more_than_zero_based_ones() {
+        run(r#"
+            a, b = list("xxabcdefyy"), list("zabcxefz")
+            zero, one = sequences.align(a, b, local=True), sequences.align(a, b, local=True, one_based=True)
+            assert (one.x_start, one.y_start) == (zero.x_start + 1, zero.y_start + 1)
+            assert (one.x_end, one.y_end) == (zero.x_end, zero.y_end)
+            assert one.x_to_y == [None if y is None else y + 1 for y in zero.x_to_y]
+            assert one.matching_runs(1) == [(x + 1, y + 1, length) for x, y, length in zero.matching_runs(1)]
+            for key in ["x_index", "y_index"]:
+                assert one.to_columns()[key] == [index + 1 for index in zero.to_columns()[key]]
+            assert one.map_position(4) == zero.map_position(3) + 1
+            (start, end), (one_start, one_end) = zero.map_range(2, 5), one.map_range(3, 5)
+            assert (one_start, one_end) == (start + 1, end)
+
+            assert raises(IndexError, zero.map_position, 10) == "x_index 10 out of range for sequence of length 10"
+            assert raises(IndexError, one.map_range, 6, 4) == "range 6..4 out of range for sequence of length 10"
+            assert "x_start 0 is not a position" in raises(IndexError, one.map_range, 0, 2)
+            # Helpers count from 0 whatever the results do.
+            assert sequences.find_all(list("abc"), list("xabc"), 3)[0].y_start == 1
+        "#);
+    }
+
+    #[test]
+    fn top_k_matches_keeps_the_best_references_in_order() {
+        run(r#"
+            query = list("kitten")
+            refs = [list("mitten"), list("kitten"), list("sitting"), list("bitten"), list("dog")]
+            ranked = lambda k: [(index, result.alignment_score) for index, result in sequences.top_k_matches(query, refs, k)]
+            # mitten and bitten tie, so the earlier one is ranked first.
+            assert ranked(2) == [(1, 6), (0, 4)]
+            assert ranked(10) == [(1, 6), (0, 4), (3, 4), (2, 1), (4, -6)]
+            assert ranked(0) == [] and sequences.top_k_matches(query, [], 3) == []
+        "#);
+    }
+
+    #[test]
+    fn local_identity_equals_percent_identity_of_a_local_alignment() {
+        run(r#"
+            import random
+            rng = random.Random(7)
+            options = [{}, {"match_score": 2, "mismatch_score": -1, "gap_score": -2},
+                       {"similarity_matrix": {("a", "c"): 1}}]
+            for _ in range(200):
+                a = [rng.choice("acgt") for _ in range(rng.randrange(12))]
+                b = [rng.choice("acgt") for _ in range(rng.randrange(12))]
+                for kwargs in options:
+                    expected = sequences.align(a, b, local=True, **kwargs).percent_identity()
+                    assert sequences.local_identity(a, b, **kwargs) == expected, (a, b, kwargs)
+        "#);
+    }
+
+    #[test]
+    #[ignore = "benchmark, run with --release --ignored --nocapture"]
+    fn bench_local_identity() {
+        run(r#"
+            import resource, time
+            peak = lambda: resource.getrusage(resource.RUSAGE_SELF).ru_maxrss
+            a = sequences.random_sequence(12000, ["a", "c", "g", "t"], 3)
+            b = sequences.random_sequence(12000, ["a", "c", "g", "t"], 4)
+            # The peak only rises, so the leaner function goes first.
+            before, start = peak(), time.perf_counter()
+            identity = sequences.local_identity(a, b)
+            identity_time, identity_growth = time.perf_counter() - start, peak() - before
+            before, start = peak(), time.perf_counter()
+            expected = sequences.align(a, b, local=True).percent_identity()
+            align_time, align_growth = time.perf_counter() - start, peak() - before
+            print(f"local_identity: {identity_time:.2f}s, +{identity_growth} KiB peak; "
+                  f"local align: {align_time:.2f}s, +{align_growth} KiB peak")
+            assert identity == expected
+            assert identity_growth * 100 < align_growth
+        "#);
+    }
+
+    #[test]
+    fn gap_anchors_place_an_indel_at_either_end_of_a_run() {
+        run(r#"
+            a, b = list("xaaay"), list("xaay")
+            left = sequences.align(a, b, gap_anchor="left")
+            right = sequences.align(a, b, gap_anchor="right")
+            assert left.alignments == [("x", "x"), ("a", "a"), ("a", "a"), ("a", "-"), ("y", "y")]
+            assert right.alignments == [("x", "x"), ("a", "-"), ("a", "a"), ("a", "a"), ("y", "y")]
+            assert left.alignment_score == right.alignment_score
+
+            for objective, gap in [("leftmost_gaps", 1), ("rightmost_gaps", 3)]:
+                for anchor in ["left", "right"]:
+                    result = sequences.align(a, b, gap_anchor=anchor, secondary_objective=objective)
+                    assert result.alignments.index(("a", "-")) == gap, (objective, anchor)
+            assert "gap_anchor must be" in raises(ValueError, sequences.align, a, b, gap_anchor="middle")
+        "#);
+    }
 }
@@ -0,0 +1,96 @@
+//! Exact-match seeding for heuristic aligners.
+
+use std::collections::HashMap;
+
+/// K-mers occurring more often than this in `b` are too repetitive to seed from.
+const MAX_SEED_OCCURRENCES: usize = 64;
+
+/// An exact match of `len` tokens starting at `x` in `a` and `y` in `b`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Anchor {
+    pub x: usize,
+    pub y: usize,
+    pub len: usize,
+}
+
+impl Anchor {
+    /// Trims the start of this anchor so that it follows `previous` in both sequences,
+    /// returning `None` if nothing of it would be left.
+    fn after(&self, previous: &Anchor) -> Option<Anchor> {
+        let x_overlap = (previous.x + previous.len).saturating_sub(self.x);
+        let y_overlap = (previous.y + previous.len).saturating_sub(self.y);
+        let overlap = x_overlap.max(y_overlap);
+        if overlap >= self.len {
+            return None;
+        }
+        Some(Anchor {
+            x: self.x + overlap,
+            y: self.y + overlap,
+            len: self.len - overlap,
+        })
+    }
+}
+
+/// Finds maximal exact matches of at least `kmer_size` tokens between `a` and `b`.
+pub(crate) fn anchors<T: Eq + std::hash::Hash>(a: &[T], b: &[T], kmer_size: usize) -> Vec<Anchor> {
+    if kmer_size == 0 || a.len() < kmer_size || b.len() < kmer_size {
+        return vec![];
+    }
+
+    let mut index: HashMap<&[T], Vec<usize>> = HashMap::new();
+    for (y, kmer) in b.windows(kmer_size).enumerate() {
+        index.entry(kmer).or_default().push(y);
+    }
+
+    let mut anchors = vec![];
+    for (x, kmer) in a.windows(kmer_size).enumerate() {
+        let ys = match index.get(kmer) {
+            Some(ys) if ys.len() <= MAX_SEED_OCCURRENCES => ys,
+            _ => continue,
+        };
+        for &y in ys {
+            // Seeds that extend a match to their left are already covered by it.
+            if x > 0 && y > 0 && a[x - 1] == b[y - 1] {
+                continue;
+            }
+            let len = a[x..]
+                .iter()
+                .zip(b[y..].iter())
+                .take_while(|(x_token, y_token)| x_token == y_token)
+                .count();
+            anchors.push(Anchor { x, y, len });
+        }
+    }
+    anchors
+}
+
+/// Picks the non-overlapping, collinear subset of `anchors` covering the most tokens, trimming
+/// the start of any overlapping the one before it.
+pub(crate) fn chain(mut anchors: Vec<Anchor>) -> Vec<Anchor> {
+    anchors.sort_by_key(|anchor| (anchor.x, anchor.y));
+
+    let mut best: Vec<(usize, Option<usize>)> = Vec::with_capacity(anchors.len());
+    for (i, anchor) in anchors.iter().enumerate() {
+        let predecessor = (0..i)
+            .filter_map(|j| anchor.after(&anchors[j]).map(|trimmed| (j, trimmed.len)))
+            .max_by_key(|&(j, len)| best[j].0 + len);
+        best.push(match predecessor {
+            Some((j, len)) if best[j].0 + len > anchor.len => (best[j].0 + len, Some(j)),
+            _ => (anchor.len, None),
+        });
+    }
+
+    let mut chain = vec![];
+    let mut next = (0..anchors.len()).max_by_key(|&i| best[i].0);
+    while let Some(i) = next {
+        next = best[i].1;
+        chain.push(match next {
+            Some(j) => anchors[i]
+                .after(&anchors[j])
+                .expect("chained anchors follow their predecessors"),
+            None => anchors[i],
+        });
+    }
+    chain.reverse();
+    chain
+}